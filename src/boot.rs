@@ -0,0 +1,128 @@
+/// Boot ROMs are mapped over the first 256 bytes of the cartridge until
+/// they write to 0xff50
+pub const BOOT_ROM_SIZE: usize = 0x100;
+
+/// The Game Boy models, whose boot ROMs hand over to the cartridge at 0x0100
+/// with different register values. Games tell them apart by A.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Model {
+    Dmg,
+    /// Game Boy Pocket
+    Mgb,
+    /// Game Boy Color, running a Color game. Only the post-boot state is
+    /// modelled, the Color's own hardware isn't emulated.
+    Cgb
+}
+
+/// CPU registers as a boot ROM leaves them
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PostBoot {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16
+}
+
+impl Model {
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name {
+            "dmg" => Some(Model::Dmg),
+            "mgb" | "pocket" => Some(Model::Mgb),
+            "cgb" | "color" => Some(Model::Cgb),
+            _ => None
+        }
+    }
+
+    /// Registers at 0x0100 for a cartridge with the given header checksum.
+    /// The DMG and MGB boot ROMs finish by adding up the header, leaving H
+    /// and C set unless its checksum byte is zero.
+    pub fn post_boot(self, header_checksum: u8) -> PostBoot {
+        let flags = if header_checksum == 0 { 0x80 } else { 0xb0 };
+        match self {
+            Model::Dmg => PostBoot { af: 0x0100 | flags, bc: 0x0013, de: 0x00d8, hl: 0x014d, sp: 0xfffe },
+            Model::Mgb => PostBoot { af: 0xff00 | flags, bc: 0x0013, de: 0x00d8, hl: 0x014d, sp: 0xfffe },
+            Model::Cgb => PostBoot { af: 0x1180, bc: 0x0000, de: 0xff56, hl: 0x000d, sp: 0xfffe }
+        }
+    }
+
+    /// The internal divider at 0x0100. The CGB boot ROM runs for longer, and
+    /// hands a Color game over with DIV at 0x26.
+    pub fn post_boot_divider(self) -> u16 {
+        match self {
+            Model::Dmg | Model::Mgb => 0xabcc,
+            Model::Cgb => 0x267c
+        }
+    }
+
+    /// I/O registers this model's boot ROM leaves differently from
+    /// `POST_BOOT_IO`, written after it
+    pub fn post_boot_io(self) -> &'static [(u16, u8)] {
+        match self {
+            Model::Dmg | Model::Mgb => &[],
+            Model::Cgb => &CGB_POST_BOOT_IO
+        }
+    }
+}
+
+/// I/O registers as the DMG and MGB boot ROMs leave them, written in order.
+/// DIV, LY and DMA are left out since writing them has side effects.
+pub const POST_BOOT_IO: [(u16, u8); 38] = [
+    (0xff00, 0xcf), // P1
+    (0xff01, 0x00), // SB
+    (0xff02, 0x7e), // SC
+    (0xff05, 0x00), // TIMA
+    (0xff06, 0x00), // TMA
+    (0xff07, 0xf8), // TAC
+    (0xff10, 0x80), // NR10
+    (0xff11, 0xbf), // NR11
+    (0xff12, 0xf3), // NR12
+    (0xff13, 0xff), // NR13
+    (0xff14, 0xbf), // NR14
+    (0xff16, 0x3f), // NR21
+    (0xff17, 0x00), // NR22
+    (0xff18, 0xff), // NR23
+    (0xff19, 0xbf), // NR24
+    (0xff1a, 0x7f), // NR30
+    (0xff1b, 0xff), // NR31
+    (0xff1c, 0x9f), // NR32
+    (0xff1d, 0xff), // NR33
+    (0xff1e, 0xbf), // NR34
+    (0xff20, 0xff), // NR41
+    (0xff21, 0x00), // NR42
+    (0xff22, 0x00), // NR43
+    (0xff23, 0xbf), // NR44
+    (0xff24, 0x77), // NR50
+    (0xff25, 0xf3), // NR51
+    (0xff26, 0xf1), // NR52
+    (0xff40, 0x91), // LCDC
+    (0xff41, 0x85), // STAT
+    (0xff42, 0x00), // SCY
+    (0xff43, 0x00), // SCX
+    (0xff45, 0x00), // LYC
+    (0xff47, 0xfc), // BGP
+    (0xff48, 0xff), // OBP0
+    (0xff49, 0xff), // OBP1
+    (0xff4a, 0x00), // WY
+    (0xff4b, 0x00), // WX
+    (0xff0f, 0xe1)  // IF, with the VBlank raised while the logo was shown
+];
+
+/// The emulated I/O registers the CGB boot ROM leaves differently
+const CGB_POST_BOOT_IO: [(u16, u8); 1] = [
+    (0xff02, 0x7f)  // SC, with the unused clock speed bit set
+];
+
+#[cfg(test)]
+mod tests {
+    use super::Model;
+
+    #[test]
+    fn header_checksum_sets_half_carry_and_carry() {
+        assert_eq!(Model::from_name("dmg").unwrap().post_boot(0x3c).af, 0x01b0);
+        assert_eq!(Model::Dmg.post_boot(0x00).af, 0x0180);
+        assert_eq!(Model::Mgb.post_boot(0x3c).af, 0xffb0);
+        assert_eq!(Model::from_name("cgb").unwrap().post_boot(0x3c).af, 0x1180);
+        assert_eq!(Model::from_name("gba"), None);
+    }
+}
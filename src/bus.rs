@@ -0,0 +1,76 @@
+use std::fmt;
+use memory_map::{ReadByte, WriteByte};
+
+/// Everything the CPU is wired to. The Game Boy's is the `MMU`, but the CPU
+/// runs just as well against plain memory or any other SM83 system.
+pub trait Bus: ReadByte + WriteByte {
+    /// Runs whatever else is on the bus for the `cycles` clock cycles an
+    /// instruction took
+    fn tick(&mut self, cycles: u8);
+
+    fn read_word(&self, address: u16) -> u16 {
+        let lower = self.read_byte(address) as u16;
+        let upper = (self.read_byte(address.wrapping_add(1)) as u16) << 8;
+        upper | lower
+    }
+
+    fn write_word(&mut self, address: u16, value: u16) {
+        self.write_byte(address, value as u8);
+        self.write_byte(address.wrapping_add(1), (value >> 8) as u8);
+    }
+}
+
+/// 64 KiB of RAM and nothing else, for running the CPU in isolation. IE and
+/// IF are ordinary bytes at 0xffff and 0xff0f.
+pub struct FlatBus {
+    memory: Box<[u8]>
+}
+
+impl FlatBus {
+    pub fn new() -> FlatBus {
+        FlatBus { memory: vec![0; 0x10000].into_boxed_slice() }
+    }
+}
+
+impl Default for FlatBus {
+    fn default() -> FlatBus {
+        FlatBus::new()
+    }
+}
+
+impl ReadByte for FlatBus {
+    fn read_byte(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+}
+
+impl WriteByte for FlatBus {
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+}
+
+impl Bus for FlatBus {
+    fn tick(&mut self, _cycles: u8) {}
+}
+
+impl fmt::Debug for FlatBus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<FlatBus>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory_map::WriteByte;
+    use super::{Bus, FlatBus};
+
+    #[test]
+    fn words_are_little_endian_and_wrap() {
+        let mut bus = FlatBus::new();
+        bus.write_word(0xffff, 0x1234);
+        assert_eq!(bus.read_word(0xffff), 0x1234);
+        bus.write_byte(0x0000, 0xab);
+        assert_eq!(bus.read_word(0xffff), 0xab34);
+    }
+}
@@ -0,0 +1,420 @@
+use std::fmt;
+use std::num::Wrapping as W;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CgbFlag {
+    DmgOnly,
+    CgbEnhanced,
+    CgbOnly
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CartridgeType {
+    RomOnly,
+    Mbc1,
+    Mbc1Ram,
+    Mbc1RamBattery,
+    Mbc2,
+    Mbc2Battery,
+    RomRam,
+    RomRamBattery,
+    Mmm01,
+    Mmm01Ram,
+    Mmm01RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
+    Mbc5,
+    Mbc5Ram,
+    Mbc5RamBattery,
+    Mbc5Rumble,
+    Mbc5RumbleRam,
+    Mbc5RumbleRamBattery,
+    Mbc6,
+    Mbc7SensorRumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery,
+    Unknown(u8)
+}
+
+impl CartridgeType {
+    pub fn from_u8(value: u8) -> CartridgeType {
+        match value {
+            0x00 => CartridgeType::RomOnly,
+            0x01 => CartridgeType::Mbc1,
+            0x02 => CartridgeType::Mbc1Ram,
+            0x03 => CartridgeType::Mbc1RamBattery,
+            0x05 => CartridgeType::Mbc2,
+            0x06 => CartridgeType::Mbc2Battery,
+            0x08 => CartridgeType::RomRam,
+            0x09 => CartridgeType::RomRamBattery,
+            0x0b => CartridgeType::Mmm01,
+            0x0c => CartridgeType::Mmm01Ram,
+            0x0d => CartridgeType::Mmm01RamBattery,
+            0x0f => CartridgeType::Mbc3TimerBattery,
+            0x10 => CartridgeType::Mbc3TimerRamBattery,
+            0x11 => CartridgeType::Mbc3,
+            0x12 => CartridgeType::Mbc3Ram,
+            0x13 => CartridgeType::Mbc3RamBattery,
+            0x19 => CartridgeType::Mbc5,
+            0x1a => CartridgeType::Mbc5Ram,
+            0x1b => CartridgeType::Mbc5RamBattery,
+            0x1c => CartridgeType::Mbc5Rumble,
+            0x1d => CartridgeType::Mbc5RumbleRam,
+            0x1e => CartridgeType::Mbc5RumbleRamBattery,
+            0x20 => CartridgeType::Mbc6,
+            0x22 => CartridgeType::Mbc7SensorRumbleRamBattery,
+            0xfc => CartridgeType::PocketCamera,
+            0xfd => CartridgeType::BandaiTama5,
+            0xfe => CartridgeType::HuC3,
+            0xff => CartridgeType::HuC1RamBattery,
+            _ => CartridgeType::Unknown(value)
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(*self,
+                 CartridgeType::Mbc1RamBattery |
+                 CartridgeType::Mbc2Battery |
+                 CartridgeType::RomRamBattery |
+                 CartridgeType::Mmm01RamBattery |
+                 CartridgeType::Mbc3TimerBattery |
+                 CartridgeType::Mbc3TimerRamBattery |
+                 CartridgeType::Mbc3RamBattery |
+                 CartridgeType::Mbc5RamBattery |
+                 CartridgeType::Mbc5RumbleRamBattery |
+                 CartridgeType::Mbc7SensorRumbleRamBattery |
+                 CartridgeType::HuC1RamBattery)
+    }
+
+    pub fn has_timer(&self) -> bool {
+        matches!(*self,
+                 CartridgeType::Mbc3TimerBattery |
+                 CartridgeType::Mbc3TimerRamBattery)
+    }
+
+    pub fn has_rumble(&self) -> bool {
+        matches!(*self,
+                 CartridgeType::Mbc5Rumble |
+                 CartridgeType::Mbc5RumbleRam |
+                 CartridgeType::Mbc5RumbleRamBattery |
+                 CartridgeType::Mbc7SensorRumbleRamBattery)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RomSize {
+    Kb32,
+    Kb64,
+    Kb128,
+    Kb256,
+    Kb512,
+    Mb1,
+    Mb2,
+    Mb4,
+    Mb8,
+    Mb1_1,
+    Mb1_2,
+    Mb1_5,
+    Unknown(u8)
+}
+
+impl RomSize {
+    pub fn from_u8(value: u8) -> RomSize {
+        match value {
+            0x00 => RomSize::Kb32,
+            0x01 => RomSize::Kb64,
+            0x02 => RomSize::Kb128,
+            0x03 => RomSize::Kb256,
+            0x04 => RomSize::Kb512,
+            0x05 => RomSize::Mb1,
+            0x06 => RomSize::Mb2,
+            0x07 => RomSize::Mb4,
+            0x08 => RomSize::Mb8,
+            0x52 => RomSize::Mb1_1,
+            0x53 => RomSize::Mb1_2,
+            0x54 => RomSize::Mb1_5,
+            _ => RomSize::Unknown(value)
+        }
+    }
+
+    /// Number of 16 KiB banks
+    pub fn banks(&self) -> Option<usize> {
+        match *self {
+            RomSize::Kb32 => Some(2),
+            RomSize::Kb64 => Some(4),
+            RomSize::Kb128 => Some(8),
+            RomSize::Kb256 => Some(16),
+            RomSize::Kb512 => Some(32),
+            RomSize::Mb1 => Some(64),
+            RomSize::Mb2 => Some(128),
+            RomSize::Mb4 => Some(256),
+            RomSize::Mb8 => Some(512),
+            RomSize::Mb1_1 => Some(72),
+            RomSize::Mb1_2 => Some(80),
+            RomSize::Mb1_5 => Some(96),
+            RomSize::Unknown(_) => None
+        }
+    }
+
+    pub fn bytes(&self) -> Option<usize> {
+        self.banks().map(|banks| banks * 0x4000)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RamSize {
+    None,
+    Kb2,
+    Kb8,
+    Kb32,
+    Kb128,
+    Kb64,
+    Unknown(u8)
+}
+
+impl RamSize {
+    pub fn from_u8(value: u8) -> RamSize {
+        match value {
+            0x00 => RamSize::None,
+            0x01 => RamSize::Kb2,
+            0x02 => RamSize::Kb8,
+            0x03 => RamSize::Kb32,
+            0x04 => RamSize::Kb128,
+            0x05 => RamSize::Kb64,
+            _ => RamSize::Unknown(value)
+        }
+    }
+
+    pub fn bytes(&self) -> Option<usize> {
+        match *self {
+            RamSize::None => Some(0),
+            RamSize::Kb2 => Some(0x800),
+            RamSize::Kb8 => Some(0x2000),
+            RamSize::Kb32 => Some(0x8000),
+            RamSize::Kb128 => Some(0x20000),
+            RamSize::Kb64 => Some(0x10000),
+            RamSize::Unknown(_) => None
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Destination {
+    Japanese,
+    Overseas,
+    Unknown(u8)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Licensee {
+    /// The one-byte code at 0x14b
+    Old(u8),
+    /// The two-character code at 0x144, used when the old code is 0x33
+    New(String)
+}
+
+/// The cartridge header at 0x0100-0x014f
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: CgbFlag,
+    pub sgb: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: RomSize,
+    pub ram_size: RamSize,
+    pub destination: Destination,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    computed_header_checksum: u8,
+    computed_global_checksum: u16
+}
+
+impl CartridgeHeader {
+    /// Parses the header from a ROM image. Bytes missing from short images
+    /// read as zero, as they do on the bus.
+    pub fn parse(rom: &[u8]) -> CartridgeHeader {
+        let byte = |address: usize| rom.get(address).cloned().unwrap_or(0);
+
+        let cgb_flag = match byte(0x0143) {
+            0x80 => CgbFlag::CgbEnhanced,
+            0xc0 => CgbFlag::CgbOnly,
+            _ => CgbFlag::DmgOnly
+        };
+
+        // CGB-era carts shortened the title to make room for a manufacturer code
+        let (title, manufacturer_code) = match cgb_flag {
+            CgbFlag::DmgOnly => (ascii(rom, 0x0134, 0x0144), None),
+            _ => (ascii(rom, 0x0134, 0x013f), Some(ascii(rom, 0x013f, 0x0143)))
+        };
+
+        let licensee = match byte(0x014b) {
+            0x33 => Licensee::New(ascii(rom, 0x0144, 0x0146)),
+            code => Licensee::Old(code)
+        };
+
+        let destination = match byte(0x014a) {
+            0x00 => Destination::Japanese,
+            0x01 => Destination::Overseas,
+            code => Destination::Unknown(code)
+        };
+
+        let mut computed_header_checksum = W(0u8);
+        for address in 0x0134..0x014d {
+            computed_header_checksum = computed_header_checksum - W(byte(address)) - W(1);
+        }
+
+        let mut computed_global_checksum = W(0u16);
+        for (address, &value) in rom.iter().enumerate() {
+            if address != 0x014e && address != 0x014f {
+                computed_global_checksum += W(value as u16);
+            }
+        }
+
+        CartridgeHeader {
+            title,
+            manufacturer_code,
+            cgb_flag,
+            sgb: byte(0x0146) == 0x03,
+            cartridge_type: CartridgeType::from_u8(byte(0x0147)),
+            rom_size: RomSize::from_u8(byte(0x0148)),
+            ram_size: RamSize::from_u8(byte(0x0149)),
+            destination,
+            licensee,
+            version: byte(0x014c),
+            header_checksum: byte(0x014d),
+            global_checksum: ((byte(0x014e) as u16) << 8) | byte(0x014f) as u16,
+            computed_header_checksum: computed_header_checksum.0,
+            computed_global_checksum: computed_global_checksum.0
+        }
+    }
+
+    /// The boot ROM refuses to start a cartridge whose header checksum is wrong
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    /// Nothing checks the global checksum on hardware, but a mismatch is a
+    /// good sign of a bad dump
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let valid = |ok: bool| if ok { "ok" } else { "MISMATCH" };
+        writeln!(f, "Title:            {}", self.title)?;
+        if let Some(ref code) = self.manufacturer_code {
+            writeln!(f, "Manufacturer:     {}", code)?;
+        }
+        writeln!(f, "CGB:              {:?}", self.cgb_flag)?;
+        writeln!(f, "SGB:              {}", self.sgb)?;
+        writeln!(f, "Cartridge type:   {:?}", self.cartridge_type)?;
+        writeln!(f, "ROM size:         {:?}", self.rom_size)?;
+        writeln!(f, "RAM size:         {:?}", self.ram_size)?;
+        writeln!(f, "Destination:      {:?}", self.destination)?;
+        match self.licensee {
+            Licensee::Old(code) => writeln!(f, "Licensee:         {:02x}", code)?,
+            Licensee::New(ref code) => writeln!(f, "Licensee:         {} (new)", code)?
+        }
+        writeln!(f, "Version:          {}", self.version)?;
+        writeln!(f, "Header checksum:  {:02x} (computed {:02x}, {})",
+                 self.header_checksum, self.computed_header_checksum,
+                 valid(self.header_checksum_valid()))?;
+        write!(f, "Global checksum:  {:04x} (computed {:04x}, {})",
+               self.global_checksum, self.computed_global_checksum,
+               valid(self.global_checksum_valid()))
+    }
+}
+
+/// Reads a NUL-padded ASCII field
+fn ascii(rom: &[u8], start: usize, end: usize) -> String {
+    (start..end)
+        .map(|address| rom.get(address).cloned().unwrap_or(0))
+        .take_while(|&b| b != 0)
+        .map(|b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '?' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CartridgeHeader, CartridgeType, CgbFlag, Destination, Licensee, RamSize, RomSize};
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x013a].copy_from_slice(b"TETRIS");
+        rom[0x0147] = 0x13;
+        rom[0x0148] = 0x05;
+        rom[0x0149] = 0x03;
+        rom[0x014a] = 0x01;
+        rom[0x014b] = 0x01;
+        rom[0x014d] = 0xef;
+        rom
+    }
+
+    #[test]
+    fn parses_fields() {
+        let header = CartridgeHeader::parse(&rom());
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb_flag, CgbFlag::DmgOnly);
+        assert!(!header.sgb);
+        assert_eq!(header.cartridge_type, CartridgeType::Mbc3RamBattery);
+        assert!(header.cartridge_type.has_battery());
+        assert_eq!(header.rom_size, RomSize::Mb1);
+        assert_eq!(header.rom_size.banks(), Some(64));
+        assert_eq!(header.ram_size, RamSize::Kb32);
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.licensee, Licensee::Old(0x01));
+    }
+
+    #[test]
+    fn parses_cgb_title_and_new_licensee() {
+        let mut rom = rom();
+        rom[0x013f..0x0143].copy_from_slice(b"AXVE");
+        rom[0x0143] = 0x80;
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        rom[0x014b] = 0x33;
+        let header = CartridgeHeader::parse(&rom);
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.manufacturer_code, Some("AXVE".to_string()));
+        assert_eq!(header.cgb_flag, CgbFlag::CgbEnhanced);
+        assert_eq!(header.licensee, Licensee::New("01".to_string()));
+    }
+
+    #[test]
+    fn verifies_checksums() {
+        let mut rom = rom();
+        let header = CartridgeHeader::parse(&rom);
+        assert!(header.header_checksum_valid());
+        assert!(!header.global_checksum_valid());
+
+        let sum = rom.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        rom[0x014e] = (sum >> 8) as u8;
+        rom[0x014f] = sum as u8;
+        let header = CartridgeHeader::parse(&rom);
+        assert!(header.global_checksum_valid());
+
+        rom[0x0147] = 0x01;
+        let header = CartridgeHeader::parse(&rom);
+        assert!(!header.header_checksum_valid());
+    }
+
+    #[test]
+    fn unknown_codes() {
+        let mut rom = rom();
+        rom[0x0147] = 0x42;
+        rom[0x0148] = 0x42;
+        let header = CartridgeHeader::parse(&rom);
+        assert_eq!(header.cartridge_type, CartridgeType::Unknown(0x42));
+        assert_eq!(header.rom_size.bytes(), None);
+    }
+}
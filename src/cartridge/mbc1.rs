@@ -0,0 +1,233 @@
+use error::EmulatorError;
+use memory_map::{ReadByte, WriteByte};
+use state::{Snapshot, StateReader, StateWriter};
+use super::{rom_byte, MemoryBankController, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+/// MBC1: up to 2 MiB of ROM and 32 KiB of RAM.
+///
+/// The 5-bit BANK1 register selects the ROM bank at 0x4000-0x7fff. The 2-bit
+/// BANK2 register supplies the upper ROM bank bits and, in mode 1, also the
+/// RAM bank and the upper bits of the bank mapped at 0x0000-0x3fff.
+pub struct Mbc1 {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    mode: bool,
+    multicart: bool
+}
+
+impl Mbc1 {
+    pub fn new(rom: Box<[u8]>, ram_size: usize) -> Mbc1 {
+        let multicart = is_multicart(&rom);
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size].into_boxed_slice(),
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart
+        }
+    }
+
+    /// Multicarts wire BANK1 as a 4-bit register, so BANK2 starts at bit 4
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn bank1_mask(&self) -> u8 {
+        if self.multicart { 0b0_1111 } else { 0b1_1111 }
+    }
+
+    fn lower_rom_bank(&self) -> usize {
+        if self.mode {
+            (self.bank2 << self.bank2_shift()) as usize
+        } else {
+            0
+        }
+    }
+
+    fn upper_rom_bank(&self) -> usize {
+        ((self.bank2 << self.bank2_shift()) | (self.bank1 & self.bank1_mask())) as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.mode { self.bank2 as usize } else { 0 }
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let offset = self.ram_bank() * RAM_BANK_SIZE + (address - 0xa000) as usize;
+        Some(offset % self.ram.len())
+    }
+}
+
+/// 8 Mbit multicarts hold several games with their own headers at banks
+/// 0x00, 0x10, 0x20 and 0x30. Finding the Nintendo logo in more than one of
+/// them means the cart uses the multicart wiring.
+fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != 64 * ROM_BANK_SIZE {
+        return false;
+    }
+    let logo = &rom[0x0104..0x0134];
+    let copies = (0..4)
+        .filter(|game| {
+            let start = game * 0x10 * ROM_BANK_SIZE + 0x0104;
+            &rom[start..start + logo.len()] == logo
+        })
+        .count();
+    copies > 1
+}
+
+impl MemoryBankController for Mbc1 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+impl Snapshot for Mbc1 {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.ram_enabled);
+        writer.u8(self.bank1);
+        writer.u8(self.bank2);
+        writer.bool(self.mode);
+        writer.bytes(&self.ram);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.ram_enabled = reader.bool()?;
+        self.bank1 = reader.u8()?;
+        self.bank2 = reader.u8()?;
+        self.mode = reader.bool()?;
+        reader.bytes(&mut self.ram)
+    }
+}
+
+impl ReadByte for Mbc1 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000...0x3fff => { rom_byte(&self.rom, self.lower_rom_bank(), address) }
+            0x4000...0x7fff => { rom_byte(&self.rom, self.upper_rom_bank(), address) }
+            0xa000...0xbfff => {
+                match self.ram_offset(address) {
+                    Some(offset) => self.ram[offset],
+                    None => 0xff
+                }
+            }
+            _ => { 0xff }
+        }
+    }
+}
+
+impl WriteByte for Mbc1 {
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000...0x1fff => { self.ram_enabled = value & 0x0f == 0x0a; }
+            0x2000...0x3fff => {
+                // Bank 0 can't be mapped to 0x4000-0x7fff, writing it selects bank 1.
+                // The check looks at all five bits, even on multicarts.
+                self.bank1 = value & 0b1_1111;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            }
+            0x4000...0x5fff => { self.bank2 = value & 0b11; }
+            0x6000...0x7fff => { self.mode = value & 0b1 == 0b1; }
+            0xa000...0xbfff => {
+                if let Some(offset) = self.ram_offset(address) {
+                    self.ram[offset] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory_map::{ReadByte, WriteByte};
+    use cartridge::banked_rom;
+    use super::Mbc1;
+
+    #[test]
+    fn bank_zero_selects_bank_one() {
+        let mut mbc = Mbc1::new(banked_rom(8), 0);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+        mbc.write_byte(0x2000, 3);
+        assert_eq!(mbc.read_byte(0x4000), 3);
+        mbc.write_byte(0x2000, 0);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+        assert_eq!(mbc.read_byte(0x0000), 0);
+    }
+
+    #[test]
+    fn bank_number_is_masked_to_rom_size() {
+        let mut mbc = Mbc1::new(banked_rom(4), 0);
+        mbc.write_byte(0x2000, 6);
+        assert_eq!(mbc.read_byte(0x4000), 2);
+        // The zero check happens before masking, so small ROMs can map bank 0 here
+        mbc.write_byte(0x2000, 0x04);
+        assert_eq!(mbc.read_byte(0x4000), 0);
+    }
+
+    #[test]
+    fn upper_bits_and_mode() {
+        let mut mbc = Mbc1::new(banked_rom(128), 0);
+        mbc.write_byte(0x2000, 0x02);
+        mbc.write_byte(0x4000, 0x01);
+        assert_eq!(mbc.read_byte(0x4000), 0x22);
+        assert_eq!(mbc.read_byte(0x0000), 0x00);
+
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(mbc.read_byte(0x0000), 0x20);
+        assert_eq!(mbc.read_byte(0x4000), 0x22);
+    }
+
+    #[test]
+    fn ram_banking() {
+        let mut mbc = Mbc1::new(banked_rom(4), 0x8000);
+        mbc.write_byte(0xa000, 0x12);
+        assert_eq!(mbc.read_byte(0xa000), 0xff);
+
+        mbc.write_byte(0x0000, 0x0a);
+        mbc.write_byte(0xa000, 0x12);
+        mbc.write_byte(0x6000, 0x01);
+        mbc.write_byte(0x4000, 0x02);
+        mbc.write_byte(0xa000, 0x34);
+        assert_eq!(mbc.read_byte(0xa000), 0x34);
+
+        // In mode 0 only bank 0 is reachable
+        mbc.write_byte(0x6000, 0x00);
+        assert_eq!(mbc.read_byte(0xa000), 0x12);
+
+        mbc.write_byte(0x0000, 0x00);
+        assert_eq!(mbc.read_byte(0xa000), 0xff);
+    }
+
+    #[test]
+    fn multicart_uses_four_bit_bank1() {
+        let mut rom = banked_rom(64).into_vec();
+        for game in 0..4 {
+            let start = game * 0x10 * 0x4000 + 0x0104;
+            for (i, byte) in rom[start..start + 0x30].iter_mut().enumerate() {
+                *byte = i as u8;
+            }
+        }
+        let mut mbc = Mbc1::new(rom.into_boxed_slice(), 0);
+        assert!(mbc.multicart);
+
+        mbc.write_byte(0x4000, 0x01);
+        mbc.write_byte(0x2000, 0x12);
+        assert_eq!(mbc.read_byte(0x4000), 0x12);
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(mbc.read_byte(0x0000), 0x10);
+    }
+}
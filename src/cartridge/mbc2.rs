@@ -0,0 +1,123 @@
+use error::EmulatorError;
+use memory_map::{ReadByte, WriteByte};
+use state::{Snapshot, StateReader, StateWriter};
+use super::{rom_byte, MemoryBankController};
+
+const RAM_SIZE: usize = 0x200;
+
+/// MBC2: up to 256 KiB of ROM and 512 half-bytes of built-in RAM.
+///
+/// A single register range at 0x0000-0x3fff enables RAM when address bit 8
+/// is clear and selects the ROM bank when it is set.
+pub struct Mbc2 {
+    rom: Box<[u8]>,
+    ram: [u8; RAM_SIZE],
+    ram_enabled: bool,
+    rom_bank: u8
+}
+
+impl Mbc2 {
+    pub fn new(rom: Box<[u8]>) -> Mbc2 {
+        Mbc2 {
+            rom,
+            ram: [0; RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1
+        }
+    }
+}
+
+impl MemoryBankController for Mbc2 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+impl Snapshot for Mbc2 {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.ram_enabled);
+        writer.u8(self.rom_bank);
+        writer.bytes(&self.ram);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.ram_enabled = reader.bool()?;
+        self.rom_bank = reader.u8()?;
+        reader.bytes(&mut self.ram)
+    }
+}
+
+impl ReadByte for Mbc2 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000...0x3fff => { rom_byte(&self.rom, 0, address) }
+            0x4000...0x7fff => { rom_byte(&self.rom, self.rom_bank as usize, address) }
+            0xa000...0xbfff if self.ram_enabled => {
+                // Only the low nibble is stored, the upper one reads as ones.
+                // The 512 bytes repeat through the whole range.
+                0xf0 | self.ram[address as usize & (RAM_SIZE - 1)]
+            }
+            _ => { 0xff }
+        }
+    }
+}
+
+impl WriteByte for Mbc2 {
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000...0x3fff => {
+                if address & 0x0100 == 0 {
+                    self.ram_enabled = value & 0x0f == 0x0a;
+                } else {
+                    self.rom_bank = value & 0x0f;
+                    if self.rom_bank == 0 {
+                        self.rom_bank = 1;
+                    }
+                }
+            }
+            0xa000...0xbfff if self.ram_enabled => {
+                self.ram[address as usize & (RAM_SIZE - 1)] = value & 0x0f;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory_map::{ReadByte, WriteByte};
+    use cartridge::banked_rom;
+    use super::Mbc2;
+
+    #[test]
+    fn address_bit_8_selects_register() {
+        let mut mbc = Mbc2::new(banked_rom(16));
+        mbc.write_byte(0x0000, 0x05);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+        mbc.write_byte(0x0100, 0x05);
+        assert_eq!(mbc.read_byte(0x4000), 5);
+        mbc.write_byte(0x2100, 0x00);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+
+        mbc.write_byte(0x0100, 0x0a);
+        mbc.write_byte(0xa000, 0x03);
+        assert_eq!(mbc.read_byte(0xa000), 0xff);
+        mbc.write_byte(0x0000, 0x0a);
+        mbc.write_byte(0xa000, 0x03);
+        assert_eq!(mbc.read_byte(0xa000), 0xf3);
+    }
+
+    #[test]
+    fn ram_is_four_bits_wide_and_repeats() {
+        let mut mbc = Mbc2::new(banked_rom(2));
+        mbc.write_byte(0x0000, 0x0a);
+        mbc.write_byte(0xa1ff, 0xab);
+        assert_eq!(mbc.read_byte(0xa1ff), 0xfb);
+        assert_eq!(mbc.read_byte(0xa3ff), 0xfb);
+        assert_eq!(mbc.read_byte(0xbfff), 0xfb);
+    }
+}
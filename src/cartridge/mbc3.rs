@@ -0,0 +1,198 @@
+use error::EmulatorError;
+use memory_map::{ReadByte, WriteByte};
+use state::{Snapshot, StateReader, StateWriter};
+use super::{rom_byte, MemoryBankController, RAM_BANK_SIZE};
+use super::rtc::Rtc;
+
+/// MBC3: up to 2 MiB of ROM, 32 KiB of RAM and an optional real-time clock.
+///
+/// Writing 0x00-0x03 to 0x4000-0x5fff maps a RAM bank at 0xa000-0xbfff,
+/// 0x08-0x0c maps one of the clock registers there instead.
+pub struct Mbc3 {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    rtc: Option<Rtc>,
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_select: u8,
+    latch: u8
+}
+
+impl Mbc3 {
+    pub fn new(rom: Box<[u8]>, ram_size: usize, rtc: Option<Rtc>) -> Mbc3 {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size].into_boxed_slice(),
+            rtc,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            latch: 0xff
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let offset = self.ram_select as usize * RAM_BANK_SIZE + (address - 0xa000) as usize;
+        Some(offset % self.ram.len())
+    }
+}
+
+impl MemoryBankController for Mbc3 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
+
+impl Snapshot for Mbc3 {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.ram_enabled);
+        writer.u8(self.rom_bank);
+        writer.u8(self.ram_select);
+        writer.u8(self.latch);
+        writer.bytes(&self.ram);
+        if let Some(ref rtc) = self.rtc {
+            rtc.write_state(writer);
+        }
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.ram_enabled = reader.bool()?;
+        self.rom_bank = reader.u8()?;
+        self.ram_select = reader.u8()?;
+        self.latch = reader.u8()?;
+        reader.bytes(&mut self.ram)?;
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.read_state(reader)?;
+        }
+        Ok(())
+    }
+}
+
+impl ReadByte for Mbc3 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000...0x3fff => { rom_byte(&self.rom, 0, address) }
+            0x4000...0x7fff => { rom_byte(&self.rom, self.rom_bank as usize, address) }
+            0xa000...0xbfff => {
+                if !self.ram_enabled {
+                    return 0xff;
+                }
+                match self.ram_select {
+                    0x00...0x03 => self.ram_offset(address).map_or(0xff, |offset| self.ram[offset]),
+                    0x08...0x0c => self.rtc.as_ref().map_or(0xff, |rtc| rtc.read(self.ram_select)),
+                    _ => 0xff
+                }
+            }
+            _ => { 0xff }
+        }
+    }
+}
+
+impl WriteByte for Mbc3 {
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000...0x1fff => { self.ram_enabled = value & 0x0f == 0x0a; }
+            0x2000...0x3fff => {
+                self.rom_bank = value & 0b0111_1111;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000...0x5fff => { self.ram_select = value; }
+            0x6000...0x7fff => {
+                // Writing 0x00 then 0x01 latches the clock
+                if self.latch == 0x00 && value == 0x01 {
+                    if let Some(ref mut rtc) = self.rtc {
+                        rtc.latch();
+                    }
+                }
+                self.latch = value;
+            }
+            0xa000...0xbfff => {
+                if !self.ram_enabled {
+                    return;
+                }
+                match self.ram_select {
+                    0x00...0x03 => {
+                        if let Some(offset) = self.ram_offset(address) {
+                            self.ram[offset] = value;
+                        }
+                    }
+                    0x08...0x0c => {
+                        if let Some(ref mut rtc) = self.rtc {
+                            rtc.write(self.ram_select, value);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory_map::{ReadByte, WriteByte};
+    use cartridge::rtc::{Rtc, TestClock};
+    use cartridge::banked_rom;
+    use super::Mbc3;
+
+    #[test]
+    fn rom_banking() {
+        let mut mbc = Mbc3::new(banked_rom(128), 0, None);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+        mbc.write_byte(0x2000, 0x45);
+        assert_eq!(mbc.read_byte(0x4000), 0x45);
+        mbc.write_byte(0x2000, 0x00);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+        assert_eq!(mbc.read_byte(0x0000), 0);
+    }
+
+    #[test]
+    fn ram_banking() {
+        let mut mbc = Mbc3::new(banked_rom(4), 0x8000, None);
+        mbc.write_byte(0x0000, 0x0a);
+        for bank in 0..4 {
+            mbc.write_byte(0x4000, bank);
+            mbc.write_byte(0xa123, 0x10 + bank);
+        }
+        for bank in 0..4 {
+            mbc.write_byte(0x4000, bank);
+            assert_eq!(mbc.read_byte(0xa123), 0x10 + bank);
+        }
+    }
+
+    #[test]
+    fn clock_registers_are_latched() {
+        let (clock, time) = TestClock::new(0);
+        let rtc = Rtc::new(Box::new(clock));
+        let mut mbc = Mbc3::new(banked_rom(4), 0x2000, Some(rtc));
+        mbc.write_byte(0x0000, 0x0a);
+        mbc.write_byte(0x4000, 0x09);
+
+        time.set(125);
+        assert_eq!(mbc.read_byte(0xa000), 0);
+        mbc.write_byte(0x6000, 0x00);
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(mbc.read_byte(0xa000), 2);
+
+        // Writing 0x01 again without 0x00 first doesn't latch
+        time.set(300);
+        mbc.write_byte(0x6000, 0x01);
+        assert_eq!(mbc.read_byte(0xa000), 2);
+        mbc.write_byte(0x4000, 0x08);
+        assert_eq!(mbc.read_byte(0xa000), 5);
+    }
+}
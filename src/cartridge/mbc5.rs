@@ -0,0 +1,158 @@
+use error::EmulatorError;
+use memory_map::{ReadByte, WriteByte};
+use state::{Snapshot, StateReader, StateWriter};
+use super::{rom_byte, MemoryBankController, RAM_BANK_SIZE};
+
+/// MBC5: up to 8 MiB of ROM and 128 KiB of RAM.
+///
+/// The ROM bank number is 9 bits, written in two halves, and bank 0 can be
+/// mapped at 0x4000-0x7fff. On rumble carts bit 3 of the RAM bank register
+/// drives the motor instead of selecting RAM.
+pub struct Mbc5 {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool
+}
+
+impl Mbc5 {
+    pub fn new(rom: Box<[u8]>, ram_size: usize, has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size].into_boxed_slice(),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let offset = self.ram_bank as usize * RAM_BANK_SIZE + (address - 0xa000) as usize;
+        Some(offset % self.ram.len())
+    }
+}
+
+impl MemoryBankController for Mbc5 {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+impl Snapshot for Mbc5 {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.ram_enabled);
+        writer.u16(self.rom_bank);
+        writer.u8(self.ram_bank);
+        writer.bool(self.rumble);
+        writer.bytes(&self.ram);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.ram_enabled = reader.bool()?;
+        self.rom_bank = reader.u16()?;
+        self.ram_bank = reader.u8()?;
+        self.rumble = reader.bool()?;
+        reader.bytes(&mut self.ram)
+    }
+}
+
+impl ReadByte for Mbc5 {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000...0x3fff => { rom_byte(&self.rom, 0, address) }
+            0x4000...0x7fff => { rom_byte(&self.rom, self.rom_bank as usize, address) }
+            0xa000...0xbfff => { self.ram_offset(address).map_or(0xff, |offset| self.ram[offset]) }
+            _ => { 0xff }
+        }
+    }
+}
+
+impl WriteByte for Mbc5 {
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000...0x1fff => { self.ram_enabled = value & 0x0f == 0x0a; }
+            0x2000...0x2fff => { self.rom_bank = (self.rom_bank & 0x100) | value as u16; }
+            0x3000...0x3fff => { self.rom_bank = (self.rom_bank & 0xff) | ((value as u16 & 0b1) << 8); }
+            0x4000...0x5fff => {
+                if self.has_rumble {
+                    self.rumble = value & 0b1000 == 0b1000;
+                    self.ram_bank = value & 0b0111;
+                } else {
+                    self.ram_bank = value & 0b1111;
+                }
+            }
+            0xa000...0xbfff => {
+                if let Some(offset) = self.ram_offset(address) {
+                    self.ram[offset] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory_map::{ReadByte, WriteByte};
+    use cartridge::MemoryBankController;
+    use cartridge::banked_rom;
+    use super::Mbc5;
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let mut mbc = Mbc5::new(banked_rom(512), 0, false);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+        mbc.write_byte(0x2000, 0x23);
+        mbc.write_byte(0x3000, 0x01);
+        assert_eq!(mbc.read_byte(0x4000), 0x23);
+        assert_eq!(mbc.read_byte(0x4001), 0x01);
+
+        // Unlike MBC1, bank 0 can be mapped to 0x4000-0x7fff
+        mbc.write_byte(0x2000, 0x00);
+        mbc.write_byte(0x3000, 0x00);
+        assert_eq!(mbc.read_byte(0x4000), 0);
+        assert_eq!(mbc.read_byte(0x4001), 0);
+    }
+
+    #[test]
+    fn sixteen_ram_banks() {
+        let mut mbc = Mbc5::new(banked_rom(2), 0x20000, false);
+        mbc.write_byte(0x0000, 0x0a);
+        for bank in 0..16 {
+            mbc.write_byte(0x4000, bank);
+            mbc.write_byte(0xa000, bank);
+        }
+        for bank in 0..16 {
+            mbc.write_byte(0x4000, bank);
+            assert_eq!(mbc.read_byte(0xa000), bank);
+        }
+    }
+
+    #[test]
+    fn rumble_bit() {
+        let mut mbc = Mbc5::new(banked_rom(2), 0x8000, true);
+        mbc.write_byte(0x0000, 0x0a);
+        mbc.write_byte(0xa000, 0x11);
+        mbc.write_byte(0x4000, 0b1000);
+        assert!(mbc.rumble());
+        assert_eq!(mbc.read_byte(0xa000), 0x11);
+        mbc.write_byte(0x4000, 0b0000);
+        assert!(!mbc.rumble());
+    }
+}
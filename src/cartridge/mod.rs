@@ -0,0 +1,386 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use error::EmulatorError;
+use memory_map::{ReadByte, WriteByte};
+use png;
+use state::{Snapshot, StateReader, StateWriter};
+
+pub use self::header::{CartridgeHeader, CartridgeType};
+pub use self::rtc::{SystemClock, TimeSource};
+use self::mbc1::Mbc1;
+use self::mbc2::Mbc2;
+use self::mbc3::Mbc3;
+use self::mbc5::Mbc5;
+use self::rom::RomOnly;
+use self::rtc::Rtc;
+
+pub mod header;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod rom;
+mod rtc;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+/// The memory bank controller wired to the cartridge's address lines. It
+/// owns the ROM and RAM and decodes every access to 0x0000-0x7fff and
+/// 0xa000-0xbfff.
+pub trait MemoryBankController: ReadByte + WriteByte + Snapshot {
+    /// The cartridge RAM, laid out as in a raw .sav file
+    fn ram(&self) -> &[u8];
+
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> { None }
+
+    /// Whether the rumble motor is currently switched on
+    fn rumble(&self) -> bool { false }
+}
+
+pub struct Cartridge {
+    header: CartridgeHeader,
+    size: usize,
+    checksum: u32,
+    mbc: Box<dyn MemoryBankController>,
+    save_path: Option<PathBuf>,
+    ram_dirty: bool
+}
+
+impl Cartridge {
+    pub fn new(rom: Box<[u8]>) -> Result<Cartridge, EmulatorError> {
+        Cartridge::with_time_source(rom, Box::new(SystemClock))
+    }
+
+    /// Creates a cartridge whose real-time clock, if it has one, reads the
+    /// time from `source`
+    pub fn with_time_source(rom: Box<[u8]>, source: Box<dyn TimeSource>) -> Result<Cartridge, EmulatorError> {
+        let header = CartridgeHeader::parse(&rom);
+        let size = rom.len();
+        let checksum = png::crc32(&rom);
+        let ram_size = header.ram_size.bytes().unwrap_or(0);
+        let cartridge_type = header.cartridge_type;
+        let mbc: Box<dyn MemoryBankController> = match cartridge_type {
+            CartridgeType::RomOnly |
+            CartridgeType::RomRam |
+            CartridgeType::RomRamBattery => Box::new(RomOnly::new(rom, ram_size)),
+            CartridgeType::Mbc1 |
+            CartridgeType::Mbc1Ram |
+            CartridgeType::Mbc1RamBattery => Box::new(Mbc1::new(rom, ram_size)),
+            CartridgeType::Mbc2 |
+            CartridgeType::Mbc2Battery => Box::new(Mbc2::new(rom)),
+            CartridgeType::Mbc3TimerBattery |
+            CartridgeType::Mbc3TimerRamBattery |
+            CartridgeType::Mbc3 |
+            CartridgeType::Mbc3Ram |
+            CartridgeType::Mbc3RamBattery => {
+                let rtc = if cartridge_type.has_timer() { Some(Rtc::new(source)) } else { None };
+                Box::new(Mbc3::new(rom, ram_size, rtc))
+            }
+            CartridgeType::Mbc5 |
+            CartridgeType::Mbc5Ram |
+            CartridgeType::Mbc5RamBattery |
+            CartridgeType::Mbc5Rumble |
+            CartridgeType::Mbc5RumbleRam |
+            CartridgeType::Mbc5RumbleRamBattery => {
+                Box::new(Mbc5::new(rom, ram_size, cartridge_type.has_rumble()))
+            }
+            _ => return Err(EmulatorError::UnsupportedCartridge(cartridge_type))
+        };
+
+        Ok(Cartridge {
+            header,
+            size,
+            checksum,
+            mbc,
+            save_path: None,
+            ram_dirty: false
+        })
+    }
+
+    /// A cartridge slot with nothing plugged in
+    pub fn empty() -> Cartridge {
+        Cartridge {
+            header: CartridgeHeader::parse(&[]),
+            size: 0,
+            checksum: png::crc32(&[]),
+            mbc: Box::new(RomOnly::new(Box::new([]), 0)),
+            save_path: None,
+            ram_dirty: false
+        }
+    }
+
+    /// Loads a ROM file. Carts with a battery keep their RAM in a .sav file
+    /// next to the ROM, which is read here if it exists.
+    pub fn load(filename: &str) -> Result<Cartridge, EmulatorError> {
+        let data = fs::read(filename)?;
+        let mut cartridge = Cartridge::new(data.into_boxed_slice())?;
+
+        if cartridge.has_battery() {
+            let save_path = Path::new(filename).with_extension("sav");
+            match fs::read(&save_path) {
+                Ok(save) => cartridge.load_save_data(&save),
+                // The game hasn't been saved yet
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(EmulatorError::Io(error))
+            }
+            cartridge.save_path = Some(save_path);
+        }
+        Ok(cartridge)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// CRC-32 of the whole ROM. Unlike the header's checksums, which many
+    /// homebrew and test ROMs leave blank, it tells any two ROMs apart.
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.header.cartridge_type.has_battery()
+    }
+
+    /// The battery-backed state in the raw .sav format: the RAM contents,
+    /// followed by the clock state on carts with a real-time clock
+    pub fn save_data(&mut self) -> Vec<u8> {
+        let mut data = self.mbc.ram().to_vec();
+        if let Some(rtc) = self.mbc.rtc_mut() {
+            data.extend(rtc.save());
+        }
+        data
+    }
+
+    /// Restores state written by `save_data` or another emulator. Saves of
+    /// the wrong size fill as much RAM as they cover.
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let length = {
+            let ram = self.mbc.ram_mut();
+            let length = ram.len().min(data.len());
+            ram[..length].copy_from_slice(&data[..length]);
+            length
+        };
+        let trailer = &data[length..];
+        if let Some(rtc) = self.mbc.rtc_mut() {
+            if trailer.len() == rtc::TRAILER_SIZE || trailer.len() == rtc::SHORT_TRAILER_SIZE {
+                rtc.load(trailer);
+            }
+        }
+        self.ram_dirty = false;
+    }
+
+    /// Writes the battery-backed state to the .sav file next to the ROM
+    pub fn save(&mut self) -> io::Result<()> {
+        if let Some(path) = self.save_path.clone() {
+            fs::write(path, self.save_data())?;
+            self.ram_dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Saves only if the game wrote to cartridge RAM since the last save
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.ram_dirty {
+            self.save()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the cartridge's rumble motor is currently switched on
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
+    }
+}
+
+impl Snapshot for Cartridge {
+    fn write_state(&self, writer: &mut StateWriter) {
+        self.mbc.write_state(writer);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.mbc.read_state(reader)?;
+        // The restored RAM likely differs from the save file
+        self.ram_dirty = true;
+        Ok(())
+    }
+}
+
+impl ReadByte for Cartridge {
+    fn read_byte(&self, address: u16) -> u8 {
+        self.mbc.read_byte(address)
+    }
+}
+
+impl WriteByte for Cartridge {
+    fn write_byte(&mut self, address: u16, value: u8) {
+        if let 0xa000...0xbfff = address {
+            self.ram_dirty = true;
+        }
+        self.mbc.write_byte(address, value)
+    }
+}
+
+/// Reads from a 16 KiB ROM bank. Bank numbers beyond the end of the ROM wrap
+/// around, as the unused bank lines aren't connected.
+fn rom_byte(rom: &[u8], bank: usize, address: u16) -> u8 {
+    if rom.is_empty() {
+        return 0xff;
+    }
+    let offset = bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1));
+    rom[offset % rom.len()]
+}
+
+/// A ROM for the banking tests. Each bank is filled with the low byte of its
+/// number, apart from the second byte, which holds the high byte.
+#[cfg(test)]
+fn banked_rom(banks: usize) -> Box<[u8]> {
+    let mut rom = vec![0; banks * ROM_BANK_SIZE];
+    for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+        for byte in chunk.iter_mut() {
+            *byte = bank as u8;
+        }
+        chunk[1] = (bank >> 8) as u8;
+    }
+    rom.into_boxed_slice()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use memory_map::{ReadByte, WriteByte};
+    use error::EmulatorError;
+    use state::{Snapshot, StateReader, StateWriter};
+    use super::{Cartridge, CartridgeType};
+
+    fn rom(cartridge_type: u8) -> Box<[u8]> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = cartridge_type;
+        rom.into_boxed_slice()
+    }
+
+    #[test]
+    fn selects_controller_from_header() {
+        for &cartridge_type in &[0x00, 0x01, 0x05, 0x08, 0x0f, 0x13, 0x19, 0x1e] {
+            assert!(Cartridge::new(rom(cartridge_type)).is_ok());
+        }
+    }
+
+    #[test]
+    fn ram_is_sized_from_header() {
+        // MBC2 has built-in RAM, whatever the header says
+        let mut cartridge = Cartridge::new(rom(0x06)).unwrap();
+        assert_eq!(cartridge.save_data().len(), 0x200);
+
+        let mut rom = rom(0x03);
+        rom[0x0149] = 0x03;
+        let mut cartridge = Cartridge::new(rom).unwrap();
+        assert_eq!(cartridge.save_data().len(), 0x8000);
+    }
+
+    #[test]
+    fn save_data_round_trip() {
+        let mut rom = rom(0x03);
+        rom[0x0149] = 0x02;
+        let mut cartridge = Cartridge::new(rom.clone()).unwrap();
+        cartridge.write_byte(0x0000, 0x0a);
+        cartridge.write_byte(0xa010, 0x42);
+        let save = cartridge.save_data();
+        assert_eq!(save[0x10], 0x42);
+
+        let mut restored = Cartridge::new(rom).unwrap();
+        restored.load_save_data(&save);
+        restored.write_byte(0x0000, 0x0a);
+        assert_eq!(restored.read_byte(0xa010), 0x42);
+    }
+
+    #[test]
+    fn save_data_appends_clock() {
+        let mut rom = rom(0x10);
+        rom[0x0149] = 0x03;
+        let mut cartridge = Cartridge::new(rom.clone()).unwrap();
+        cartridge.write_byte(0x0000, 0x0a);
+        cartridge.write_byte(0x4000, 0x0a);
+        cartridge.write_byte(0xa000, 0x07);
+        let save = cartridge.save_data();
+        assert_eq!(save.len(), 0x8000 + 48);
+        assert_eq!(save[0x8000 + 8], 0x07);
+
+        let mut restored = Cartridge::new(rom).unwrap();
+        restored.load_save_data(&save);
+        restored.write_byte(0x0000, 0x0a);
+        restored.write_byte(0x4000, 0x0a);
+        restored.write_byte(0x6000, 0x00);
+        restored.write_byte(0x6000, 0x01);
+        assert_eq!(restored.read_byte(0xa000), 0x07);
+    }
+
+    #[test]
+    fn load_reads_save_file_next_to_rom() {
+        let directory = env::temp_dir().join(format!("gbrs-save-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let rom_path = directory.join("game.gb");
+        let mut rom = rom(0x03);
+        rom[0x0149] = 0x02;
+        fs::write(&rom_path, &rom).unwrap();
+        let mut save = vec![0; 0x2000];
+        save[0] = 0x99;
+        fs::write(directory.join("game.sav"), &save).unwrap();
+
+        let mut cartridge = Cartridge::load(rom_path.to_str().unwrap()).unwrap();
+        cartridge.write_byte(0x0000, 0x0a);
+        assert_eq!(cartridge.read_byte(0xa000), 0x99);
+
+        cartridge.write_byte(0xa001, 0x55);
+        cartridge.flush().unwrap();
+        assert_eq!(fs::read(directory.join("game.sav")).unwrap()[1], 0x55);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn snapshot_restores_banking_and_ram() {
+        let mut rom = vec![0; 0x20000];
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+        for bank in 0..8 {
+            rom[bank * 0x4000 + 0x100] = bank as u8;
+        }
+        let rom = rom.into_boxed_slice();
+        let mut cartridge = Cartridge::new(rom.clone()).unwrap();
+        cartridge.write_byte(0x0000, 0x0a);
+        cartridge.write_byte(0x2000, 0x05);
+        cartridge.write_byte(0xa000, 0x42);
+        let mut writer = StateWriter::new();
+        cartridge.write_state(&mut writer);
+        let state = writer.into_inner();
+
+        let mut restored = Cartridge::new(rom).unwrap();
+        restored.read_state(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(restored.read_byte(0x4100), 0x05);
+        assert_eq!(restored.read_byte(0xa000), 0x42);
+    }
+
+    #[test]
+    fn rejects_unsupported_types() {
+        match Cartridge::new(rom(0x20)) {
+            Err(EmulatorError::UnsupportedCartridge(cartridge_type)) => {
+                assert_eq!(cartridge_type, CartridgeType::Mbc6);
+            }
+            Err(error) => panic!("Unexpected error: {}", error),
+            Ok(_) => panic!("MBC6 cartridge loaded")
+        }
+        match Cartridge::new(rom(0x42)) {
+            Err(error) => assert_eq!(error.to_string(), "Unknown cartridge type 42"),
+            Ok(_) => panic!("Unknown cartridge loaded")
+        }
+    }
+}
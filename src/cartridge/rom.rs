@@ -0,0 +1,86 @@
+use error::EmulatorError;
+use memory_map::{ReadByte, WriteByte};
+use state::{Snapshot, StateReader, StateWriter};
+use super::MemoryBankController;
+
+/// A cartridge without a memory bank controller: 32 KiB of ROM mapped
+/// directly, and optionally up to 8 KiB of RAM that is always accessible.
+pub struct RomOnly {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>
+}
+
+impl RomOnly {
+    pub fn new(rom: Box<[u8]>, ram_size: usize) -> RomOnly {
+        RomOnly {
+            rom,
+            ram: vec![0; ram_size].into_boxed_slice()
+        }
+    }
+}
+
+impl MemoryBankController for RomOnly {
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+impl Snapshot for RomOnly {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.ram);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        reader.bytes(&mut self.ram)
+    }
+}
+
+impl ReadByte for RomOnly {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0x0000...0x7fff => { self.rom.get(address as usize).cloned().unwrap_or(0xff) }
+            0xa000...0xbfff => { self.ram.get((address - 0xa000) as usize).cloned().unwrap_or(0xff) }
+            _ => { 0xff }
+        }
+    }
+}
+
+impl WriteByte for RomOnly {
+    fn write_byte(&mut self, address: u16, value: u8) {
+        // Without a controller there are no registers to write
+        if let 0xa000...0xbfff = address {
+            if let Some(byte) = self.ram.get_mut((address - 0xa000) as usize) {
+                *byte = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory_map::{ReadByte, WriteByte};
+    use super::RomOnly;
+
+    #[test]
+    fn ram_is_always_accessible() {
+        let mut cart = RomOnly::new(vec![0x12; 0x8000].into_boxed_slice(), 0x2000);
+        cart.write_byte(0x2000, 0x05);
+        assert_eq!(cart.read_byte(0x7fff), 0x12);
+
+        cart.write_byte(0xa000, 0x34);
+        cart.write_byte(0xbfff, 0x56);
+        assert_eq!(cart.read_byte(0xa000), 0x34);
+        assert_eq!(cart.read_byte(0xbfff), 0x56);
+    }
+
+    #[test]
+    fn missing_ram_reads_open_bus() {
+        let mut cart = RomOnly::new(vec![0; 0x8000].into_boxed_slice(), 0);
+        cart.write_byte(0xa000, 0x34);
+        assert_eq!(cart.read_byte(0xa000), 0xff);
+    }
+}
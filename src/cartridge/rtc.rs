@@ -0,0 +1,323 @@
+#[cfg(test)]
+use std::cell::Cell;
+#[cfg(test)]
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use error::EmulatorError;
+use state::{Snapshot, StateReader, StateWriter};
+
+/// Supplies the current time, in seconds, to a real-time clock
+pub trait TimeSource {
+    fn now(&self) -> u64;
+}
+
+/// Wall clock time, in seconds since the Unix epoch
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+    }
+}
+
+/// A time source that only moves when a test sets it
+#[cfg(test)]
+pub struct TestClock(Rc<Cell<u64>>);
+
+#[cfg(test)]
+impl TestClock {
+    /// A clock reading `start`, and a handle for setting the time
+    pub fn new(start: u64) -> (TestClock, Rc<Cell<u64>>) {
+        let time = Rc::new(Cell::new(start));
+        (TestClock(time.clone()), time)
+    }
+}
+
+#[cfg(test)]
+impl TimeSource for TestClock {
+    fn now(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+/// Size of the RTC state appended to a save file
+pub const TRAILER_SIZE: usize = 48;
+/// Size of the same state with a 32-bit timestamp
+pub const SHORT_TRAILER_SIZE: usize = 44;
+
+const HALT: u8 = 0b0100_0000;
+const DAY_CARRY: u8 = 0b1000_0000;
+
+/// The MBC3 real-time clock.
+///
+/// Time is only brought up to date when the game latches or writes the
+/// registers, by adding the seconds elapsed on the time source since the
+/// previous update. Reads see the latched copy.
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    latched: [u8; 5],
+    last_update: u64,
+    source: Box<dyn TimeSource>
+}
+
+impl Rtc {
+    pub fn new(source: Box<dyn TimeSource>) -> Rtc {
+        Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            last_update: source.now(),
+            source
+        }
+    }
+
+    /// Reads one of the latched registers, 0x08-0x0c
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08...0x0c => self.latched[(register - 0x08) as usize],
+            _ => 0xff
+        }
+    }
+
+    /// Writes one of the live registers, 0x08-0x0c
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.update();
+        match register {
+            0x08 => { self.seconds = value & 0b0011_1111; }
+            0x09 => { self.minutes = value & 0b0011_1111; }
+            0x0a => { self.hours = value & 0b0001_1111; }
+            0x0b => { self.days = (self.days & 0x100) | value as u16; }
+            0x0c => {
+                self.days = (self.days & 0xff) | ((value as u16 & 0b1) << 8);
+                self.halted = value & HALT == HALT;
+                self.day_carry = value & DAY_CARRY == DAY_CARRY;
+            }
+            _ => {}
+        }
+    }
+
+    /// Copies the live registers into the ones the game reads
+    pub fn latch(&mut self) {
+        self.update();
+        self.latched = self.registers();
+    }
+
+    /// Serializes the clock in the trailer format appended to .sav files by
+    /// most emulators: the live and latched registers as little-endian 32-bit
+    /// words, followed by a 64-bit Unix timestamp
+    pub fn save(&mut self) -> Vec<u8> {
+        self.update();
+        let mut trailer = Vec::with_capacity(TRAILER_SIZE);
+        for &register in self.registers().iter().chain(self.latched.iter()) {
+            trailer.extend_from_slice(&(register as u32).to_le_bytes());
+        }
+        trailer.extend_from_slice(&self.last_update.to_le_bytes());
+        trailer
+    }
+
+    /// Restores a clock saved by `save`. Older emulators wrote a 32-bit
+    /// timestamp, so 44 byte trailers are accepted too. The time passed since
+    /// the timestamp is added on the next update.
+    pub fn load(&mut self, trailer: &[u8]) {
+        if trailer.len() < SHORT_TRAILER_SIZE {
+            return;
+        }
+        let word = |index: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&trailer[index * 4..index * 4 + 4]);
+            u32::from_le_bytes(bytes)
+        };
+
+        self.seconds = word(0) as u8 & 0b0011_1111;
+        self.minutes = word(1) as u8 & 0b0011_1111;
+        self.hours = word(2) as u8 & 0b0001_1111;
+        self.days = ((word(4) as u16 & 0b1) << 8) | (word(3) as u16 & 0xff);
+        self.halted = word(4) as u8 & HALT == HALT;
+        self.day_carry = word(4) as u8 & DAY_CARRY == DAY_CARRY;
+        for i in 0..5 {
+            self.latched[i] = word(5 + i) as u8;
+        }
+        self.last_update = if trailer.len() >= TRAILER_SIZE {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&trailer[40..48]);
+            u64::from_le_bytes(bytes)
+        } else {
+            word(10) as u64
+        };
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        let mut flags = (self.days >> 8) as u8 & 0b1;
+        if self.halted { flags |= HALT; }
+        if self.day_carry { flags |= DAY_CARRY; }
+        [self.seconds, self.minutes, self.hours, self.days as u8, flags]
+    }
+
+    fn update(&mut self) {
+        let now = self.source.now();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+        if !self.halted {
+            self.advance(elapsed);
+        }
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        let seconds = self.seconds as u64 + seconds;
+        let minutes = self.minutes as u64 + seconds / 60;
+        let hours = self.hours as u64 + minutes / 60;
+        let days = self.days as u64 + hours / 24;
+
+        self.seconds = (seconds % 60) as u8;
+        self.minutes = (minutes % 60) as u8;
+        self.hours = (hours % 24) as u8;
+        // The day counter is 9 bits, overflowing sets the carry until the game clears it
+        if days > 0x1ff {
+            self.day_carry = true;
+        }
+        self.days = (days & 0x1ff) as u16;
+    }
+}
+
+/// Like the .sav trailer, snapshots store the registers as of the last
+/// update along with its timestamp, without bringing the clock up to date
+/// first. The clock keeps following the time source, so a restored RTC
+/// catches up on the time since that timestamp when it's next latched or
+/// written, and loading an old state doesn't wind it back.
+impl Snapshot for Rtc {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.registers());
+        writer.bytes(&self.latched);
+        writer.u64(self.last_update);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        let mut registers = [0; 5];
+        reader.bytes(&mut registers)?;
+        self.seconds = registers[0];
+        self.minutes = registers[1];
+        self.hours = registers[2];
+        self.days = ((registers[4] as u16 & 0b1) << 8) | registers[3] as u16;
+        self.halted = registers[4] & HALT == HALT;
+        self.day_carry = registers[4] & DAY_CARRY == DAY_CARRY;
+        reader.bytes(&mut self.latched)?;
+        self.last_update = reader.u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use super::{Rtc, TestClock};
+
+    fn rtc() -> (Rtc, Rc<Cell<u64>>) {
+        let (clock, time) = TestClock::new(1000);
+        (Rtc::new(Box::new(clock)), time)
+    }
+
+    #[test]
+    fn counts_elapsed_time() {
+        let (mut rtc, time) = rtc();
+        time.set(1000 + 2 * 86400 + 3 * 3600 + 4 * 60 + 5);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 5);
+        assert_eq!(rtc.read(0x09), 4);
+        assert_eq!(rtc.read(0x0a), 3);
+        assert_eq!(rtc.read(0x0b), 2);
+        assert_eq!(rtc.read(0x0c), 0);
+    }
+
+    #[test]
+    fn reads_are_latched() {
+        let (mut rtc, time) = rtc();
+        time.set(1010);
+        rtc.latch();
+        time.set(1020);
+        assert_eq!(rtc.read(0x08), 10);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 20);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let (mut rtc, time) = rtc();
+        rtc.write(0x0c, 0b0100_0000);
+        time.set(2000);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 0);
+        assert_eq!(rtc.read(0x0c), 0b0100_0000);
+
+        rtc.write(0x0c, 0);
+        time.set(2030);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 30);
+    }
+
+    #[test]
+    fn save_and_load_trailer() {
+        let (mut saved, time) = rtc();
+        saved.write(0x0a, 5);
+        time.set(1042);
+        saved.latch();
+        let trailer = saved.save();
+        assert_eq!(trailer.len(), 48);
+        assert_eq!(&trailer[0..4], &[42, 0, 0, 0]);
+        assert_eq!(&trailer[8..12], &[5, 0, 0, 0]);
+        assert_eq!(&trailer[20..24], &[42, 0, 0, 0]);
+        assert_eq!(&trailer[40..48], &1042u64.to_le_bytes());
+
+        // Time keeps passing while the game isn't running
+        let (mut restored, time) = rtc();
+        restored.load(&trailer);
+        assert_eq!(restored.read(0x08), 42);
+        time.set(1042 + 60);
+        restored.latch();
+        assert_eq!(restored.read(0x08), 42);
+        assert_eq!(restored.read(0x09), 1);
+        assert_eq!(restored.read(0x0a), 5);
+    }
+
+    #[test]
+    fn loads_short_trailer() {
+        let (mut rtc, _) = rtc();
+        let mut trailer = vec![0; 44];
+        trailer[0] = 30;
+        trailer[16] = 0b1000_0001;
+        trailer[40..44].copy_from_slice(&1000u32.to_le_bytes());
+        rtc.load(&trailer);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 30);
+        assert_eq!(rtc.read(0x0c), 0b1000_0001);
+    }
+
+    #[test]
+    fn day_counter_overflow_sets_carry() {
+        let (mut rtc, time) = rtc();
+        rtc.write(0x0b, 0xff);
+        rtc.write(0x0c, 0x01);
+        time.set(1000 + 86400);
+        rtc.latch();
+        assert_eq!(rtc.read(0x0b), 0);
+        assert_eq!(rtc.read(0x0c), 0b1000_0000);
+
+        // The carry stays set until it's written
+        time.set(1000 + 2 * 86400);
+        rtc.latch();
+        assert_eq!(rtc.read(0x0b), 1);
+        assert_eq!(rtc.read(0x0c), 0b1000_0000);
+        rtc.write(0x0c, 0);
+        rtc.latch();
+        assert_eq!(rtc.read(0x0c), 0);
+    }
+}
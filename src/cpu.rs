@@ -1,52 +1,62 @@
 use std::num::Wrapping as W;
 
+use boot::Model;
+use bus::Bus;
 use data::Data;
-use debugger;
-use memory_map::{ReadByte, WriteByte};
+use debugger::Hook;
+use error::EmulatorError;
+use interrupt::Interrupt;
+use memory_map::ReadByte;
 use mmu::MMU;
+use state::{self, Snapshot, StateReader, StateWriter};
 
 trait AddressingMode {
-  fn load(&self, cpu: &mut CPU) -> Data;
-  fn store(&self, cpu: &mut CPU, value: Data);
+  fn load<B: Bus>(&self, cpu: &mut CPU<B>) -> Data;
+  fn store<B: Bus>(&self, cpu: &mut CPU<B>, value: Data);
+
+  /// Cycles spent on each bus access to the operand, beyond the opcode fetch
+  fn access_cycles(&self) -> u8 {
+    0
+  }
 }
 
 struct ImmediateAddressingMode;
 impl AddressingMode for ImmediateAddressingMode {
-  fn load(&self, cpu: &mut CPU) -> Data {
+  fn load<B: Bus>(&self, cpu: &mut CPU<B>) -> Data {
     Data::Byte(cpu.take_byte())
   }
-  fn store(&self, _: &mut CPU, _: Data) {
-    panic!("Can't write to ROM!")
+  fn store<B: Bus>(&self, _: &mut CPU<B>, _: Data) {
+    unreachable!("Can't write to ROM!")
   }
 }
 
 struct ImmediateSignedAddressingMode;
 impl AddressingMode for ImmediateSignedAddressingMode {
-  fn load(&self, cpu: &mut CPU) -> Data {
+  fn load<B: Bus>(&self, cpu: &mut CPU<B>) -> Data {
     Data::SignedByte(cpu.take_byte() as i8)
   }
-  fn store(&self, _: &mut CPU, _: Data) {
-    panic!("Can't write to ROM!")
+  fn store<B: Bus>(&self, _: &mut CPU<B>, _: Data) {
+    unreachable!("Can't write to ROM!")
   }
 }
 
 struct ImmediateWordAddressingMode;
 impl AddressingMode for ImmediateWordAddressingMode {
-  fn load(&self, cpu: &mut CPU) -> Data {
+  fn load<B: Bus>(&self, cpu: &mut CPU<B>) -> Data {
     Data::Word(cpu.take_word())
   }
-  fn store(&self, _: &mut CPU, _: Data) {
-    panic!("Can't write to ROM!")
+  fn store<B: Bus>(&self, _: &mut CPU<B>, _: Data) {
+    unreachable!("Can't write to ROM!")
   }
 }
 
 struct ImmediateWordAddressAddressingMode;
 impl AddressingMode for ImmediateWordAddressAddressingMode {
-  fn load(&self, cpu: &mut CPU) -> Data {
+  fn load<B: Bus>(&self, cpu: &mut CPU<B>) -> Data {
     let am = MemoryAddressingMode { address: cpu.take_word() };
     am.load(cpu)
   }
-  fn store(&self, cpu: &mut CPU, value: Data) {
+  fn store<B: Bus>(&self, cpu: &mut CPU<B>, value: Data) {
     let am = MemoryAddressingMode { address: cpu.take_word() };
     am.store(cpu, value);
   }
@@ -57,16 +67,20 @@ struct MemoryAddressingMode {
 }
 
 impl AddressingMode for MemoryAddressingMode {
-  fn load(&self, cpu: &mut CPU) -> Data {
+  fn load<B: Bus>(&self, cpu: &mut CPU<B>) -> Data {
     Data::Byte(cpu.mmu.read_byte(self.address))
   }
 
-  fn store(&self, cpu: &mut CPU, value: Data) {
+  fn store<B: Bus>(&self, cpu: &mut CPU<B>, value: Data) {
     match value {
       Data::Byte(b) => cpu.mmu.write_byte(self.address, b),
       _ => {}
     }
   }
+
+  fn access_cycles(&self) -> u8 {
+    4
+  }
 }
 
 struct RegisterAdressingMode {
@@ -74,7 +88,7 @@ struct RegisterAdressingMode {
 }
 
 impl AddressingMode for RegisterAdressingMode {
-  fn load(&self, cpu: &mut CPU) -> Data {
+  fn load<B: Bus>(&self, cpu: &mut CPU<B>) -> Data {
     let val = match self.register {
       Register::A => cpu.registers.a,
       Register::B => cpu.registers.b,
@@ -87,7 +101,7 @@ impl AddressingMode for RegisterAdressingMode {
     Data::Byte(val)
   }
 
-  fn store(&self, cpu: &mut CPU, value: Data) {
+  fn store<B: Bus>(&self, cpu: &mut CPU<B>, value: Data) {
     if let Data::Byte(b) = value {
       match self.register {
         Register::A => cpu.registers.a = b,
@@ -103,21 +117,37 @@ impl AddressingMode for RegisterAdressingMode {
 }
 
 struct SixteenBitRegisterAdressingMode {
-  value: u16
+  register: SixteenBitRegister
 }
 
 impl AddressingMode for SixteenBitRegisterAdressingMode {
-  fn load(&self, _: &mut CPU) -> Data {
-    Data::Word(self.value)
+  fn load<B: Bus>(&self, cpu: &mut CPU<B>) -> Data {
+    let r = &cpu.registers;
+    let val = match self.register {
+      SixteenBitRegister::AF => r.af(),
+      SixteenBitRegister::BC => r.bc(),
+      SixteenBitRegister::DE => r.de(),
+      SixteenBitRegister::HL => r.hl()
+    };
+    Data::Word(val)
   }
 
-  fn store(&self, _cpu: &mut CPU, _value: Data) {
-    panic!("Can't write registers yet");
+  fn store<B: Bus>(&self, cpu: &mut CPU<B>, value: Data) {
+    if let Data::Word(w) = value {
+      let r = &mut cpu.registers;
+      match self.register {
+        SixteenBitRegister::AF => r.set_af(w),
+        SixteenBitRegister::BC => r.set_bc(w),
+        SixteenBitRegister::DE => r.set_de(w),
+        SixteenBitRegister::HL => r.set_hl(w)
+      }
+    }
   }
 }
 #[derive(Debug)]
 pub struct Clock {
-  m: u16,
+  // Cycles since power on
+  m: u64,
   t: u16
 }
 
@@ -130,22 +160,22 @@ impl Clock {
   }
 }
 
-#[derive(Debug)]
-pub struct Flags {
-  pub z: bool,
-  pub n: bool,
-  pub h: bool,
-  pub c: bool
+/// The flags kept in the upper nibble of F. The discriminant is the flag's bit.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Flag {
+  /// Zero
+  Z = 7,
+  /// Subtract, for DAA
+  N = 6,
+  /// Half carry, out of bit 3 or bit 11
+  H = 5,
+  /// Carry
+  C = 4
 }
 
-impl Flags {
-  fn new() -> Flags {
-    Flags {
-      z: false,
-      n: false,
-      h: false,
-      c: false
-    }
+impl Flag {
+  pub fn mask(self) -> u8 {
+    1 << (self as u8)
   }
 }
 
@@ -154,6 +184,9 @@ pub struct Registers {
   pub pc: u16, // Program Counter
   pub sp: u16, // Stack pointer
   pub a: u8,
+  // Flags. The lower nibble doesn't exist and always reads 0, so it's only
+  // written through `set_f`.
+  f: u8,
   pub b: u8,
   pub c: u8,
   pub d: u8,
@@ -166,6 +199,10 @@ enum Register {
   A, B, C, D, E, H, L
 }
 
+enum SixteenBitRegister {
+  AF, BC, DE, HL
+}
+
 impl Registers {
   fn new() -> Registers {
     Registers {
@@ -173,6 +210,7 @@ impl Registers {
       sp: 0,
       // Registers
       a: 0,
+      f: 0,
       b: 0,
       c: 0,
       d: 0,
@@ -181,56 +219,237 @@ impl Registers {
       l: 0,
     }
   }
+
+  pub fn f(&self) -> u8 {
+    self.f
+  }
+
+  pub fn set_f(&mut self, value: u8) {
+    self.f = value & 0xf0;
+  }
+
+  pub fn flag(&self, flag: Flag) -> bool {
+    self.f & flag.mask() != 0
+  }
+
+  pub fn set_flag(&mut self, flag: Flag, value: bool) {
+    if value {
+      self.f |= flag.mask();
+    } else {
+      self.f &= !flag.mask();
+    }
+  }
+
+  // 16-bit register pairs
+
+  pub fn af(&self) -> u16 {
+    (self.a as u16) << 8 | self.f as u16
+  }
+
+  pub fn bc(&self) -> u16 {
+    (self.b as u16) << 8 | self.c as u16
+  }
+
+  pub fn de(&self) -> u16 {
+    (self.d as u16) << 8 | self.e as u16
+  }
+
+  pub fn hl(&self) -> u16 {
+    (self.h as u16) << 8 | self.l as u16
+  }
+
+  pub fn set_af(&mut self, value: u16) {
+    self.a = (value >> 8) as u8;
+    self.set_f(value as u8);
+  }
+
+  pub fn set_bc(&mut self, value: u16) {
+    self.b = (value >> 8) as u8;
+    self.c = value as u8;
+  }
+
+  pub fn set_de(&mut self, value: u16) {
+    self.d = (value >> 8) as u8;
+    self.e = value as u8;
+  }
+
+  pub fn set_hl(&mut self, value: u16) {
+    self.h = (value >> 8) as u8;
+    self.l = value as u8;
+  }
 }
 
+/// What the CPU is currently doing, as far as the rest of the system can tell
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Status {
+  Running,
+  Halted,
+  Stopped,
+  /// Executed an illegal opcode and stopped fetching instructions for good
+  Locked(u8)
+}
+
+/// An SM83 core, running against the Game Boy's `MMU` unless another bus is
+/// plugged in
 #[derive(Debug)]
-pub struct CPU {
-  pub mmu: MMU,
+pub struct CPU<B = MMU> {
+  pub mmu: B,
   clock: Clock,
   pub registers: Registers,
 
   // Internal clock
   m: u8,
 
-  pub flags: Flags,
   pub interrupts: bool,
-
-  pub stopped: bool
+  // EI sets IME only once the instruction after it has run. Counts down the
+  // instructions left, including the EI itself.
+  ime_delay: u8,
+
+  pub halted: bool,
+  halt_bug: bool,
+  pub stopped: bool,
+  // The illegal opcode that locked the CPU, and its address
+  locked: Option<(u8, u16)>
 }
 
-impl CPU {
-  pub fn new(mmu: MMU) -> CPU {
+impl<B: Bus> CPU<B> {
+  pub fn new(mmu: B) -> CPU<B> {
     let clock = Clock::new();
-    let flags = Flags::new();
     CPU {
       mmu: mmu,
       clock: clock,
       registers: Registers::new(),
       m: 0,
-      flags: flags,
-      interrupts: true,
-      stopped: false
+      interrupts: false,
+      ime_delay: 0,
+      halted: false,
+      halt_bug: false,
+      stopped: false,
+      locked: None
+    }
+  }
+
+  /// Clock cycles run since power on, at 4.194304 MHz
+  pub fn cycles(&self) -> u64 {
+    self.clock.m
+  }
+
+  pub fn status(&self) -> Status {
+    match self.locked {
+      Some((op, _)) => Status::Locked(op),
+      None if self.stopped => Status::Stopped,
+      None if self.halted => Status::Halted,
+      None => Status::Running
     }
   }
 
-  pub fn step(&mut self, debugger: &mut debugger::Debugger) {
+  /// Executes one instruction, or idles for one M-cycle while halted or
+  /// stopped. Fails once the CPU has locked up on an illegal opcode.
+  pub fn step<H: Hook<B>>(&mut self, debugger: &mut H) -> Result<(), EmulatorError> {
     self.m = 0;
-    debugger.set_pc(self.registers.pc);
+    debugger.between_instructions(self);
+
+    // A locked CPU never fetches again, but the rest of the system keeps running
+    if self.locked.is_some() {
+      self.m = 4;
+      self.tick();
+      return self.check_locked();
+    }
+
+    // STOP waits for a selected joypad line to go low. Idle steps still take
+    // an M-cycle, so anything running the CPU against the clock can time out.
+    if self.stopped {
+      if self.mmu.read_byte(0xff00) & 0x0f == 0x0f {
+        self.m = 4;
+        self.tick();
+        return Ok(());
+      }
+      self.stopped = false;
+    }
+
+    // HALT keeps clocking the rest of the system until an interrupt is pending,
+    // whether or not IME allows it to be serviced
+    if self.halted {
+      if self.pending_interrupts() == 0 {
+        self.m = 4;
+        self.tick();
+        return Ok(());
+      }
+      self.halted = false;
+    }
 
-    // Automatically break if we enter invalid program address space
-    if self.registers.pc >= 0x7fff {
-      debugger.add_pc_break(self.registers.pc);
+    if self.handle_interrupts() {
+      self.tick();
+      return Ok(());
     }
 
+    let pc = self.registers.pc;
     let instruction = self.take_byte();
 
-    debugger.set_instruction(instruction);
-    debugger.debug(self);
+    // The HALT bug fails to increment PC after the opcode fetch
+    if self.halt_bug {
+      self.halt_bug = false;
+      self.registers.pc = (W(self.registers.pc) - W(1)).0;
+    }
+
+    // The debugger loaded another state, which resumes from its own PC
+    if debugger.before_instruction(self, pc, instruction) {
+      return Ok(());
+    }
 
     decode_op!(instruction, self);
 
-    self.clock.m = (W(self.clock.m) + W(self.m as u16)).0;
-    self.mmu.step(self.m);
+    if self.ime_delay > 0 {
+      self.ime_delay -= 1;
+      if self.ime_delay == 0 {
+        self.interrupts = true;
+      }
+    }
+
+    self.tick();
+    self.check_locked()
+  }
+
+  fn check_locked(&self) -> Result<(), EmulatorError> {
+    match self.locked {
+      Some((opcode, address)) => Err(EmulatorError::IllegalOpcode { opcode, address }),
+      None => Ok(())
+    }
+  }
+
+  /// Advances the clock and the rest of the system by the cycles taken this step
+  fn tick(&mut self) {
+    self.clock.m += self.m as u64;
+    self.mmu.tick(self.m);
+  }
+
+  // Interrupts
+
+  /// Services the highest priority interrupt that is both enabled in IE and
+  /// requested in IF, provided IME is set. Dispatch clears the request bit and
+  /// IME, pushes PC and jumps to the interrupt's vector, taking 5 M-cycles.
+  fn handle_interrupts(&mut self) -> bool {
+    if !self.interrupts {
+      return false;
+    }
+
+    match Interrupt::from_pending(self.pending_interrupts()) {
+      Some(interrupt) => {
+        let requested = self.mmu.read_byte(0xff0f);
+        self.mmu.write_byte(0xff0f, requested & !interrupt.mask());
+        self.interrupts = false;
+        let pc = self.registers.pc;
+        self.push_word(pc);
+        self.registers.pc = interrupt.vector();
+        self.m = 20;
+        true
+      }
+      None => false
+    }
+  }
+
+  fn pending_interrupts(&self) -> u8 {
+    self.mmu.read_byte(0xffff) & self.mmu.read_byte(0xff0f) & 0x1f
   }
 
   // Fetch from program
@@ -239,22 +458,22 @@ impl CPU {
     let immediate = self.mmu.read_byte(self.registers.pc);
     self.registers.pc = (W(self.registers.pc) + W(1)).0;
     self.m = (W(self.m) + W(4)).0;
-    return immediate;
+    immediate
   }
 
   fn take_word(&mut self) -> u16 {
     let immediate = self.mmu.read_word(self.registers.pc);
     self.registers.pc = (W(self.registers.pc) + W(2)).0;
     self.m = (W(self.m) + W(8)).0;
-    return immediate;
+    immediate
   }
 
   // Pop off stack
 
   fn pop_byte(&mut self) -> u8 {
     let value = self.mmu.read_byte(self.registers.sp);
-    self.registers.sp = self.registers.sp + 1;
-    return value;
+    self.registers.sp = self.registers.sp.wrapping_add(1);
+    value
   }
 
   fn pop_word(&mut self) -> u16 {
@@ -355,66 +574,22 @@ impl CPU {
     self.register(Register::A)
   }
 
-  fn register_hl(&self) -> SixteenBitRegisterAdressingMode {
-    let hl = ((self.registers.h as u16) << 8) + self.registers.l as u16;
-    SixteenBitRegisterAdressingMode { value: hl }
-  }
-
-  // 16-bit register sets
-
-  fn set_bc(&mut self, value: u16) {
-    self.registers.b = get_upper_bytes(value);
-    self.registers.c = get_lower_bytes(value);
-  }
-
-  fn set_de(&mut self, value: u16) {
-    self.registers.d = get_upper_bytes(value);
-    self.registers.e = get_lower_bytes(value);
-  }
-
-  fn set_hl(&mut self, value: u16) {
-    self.registers.h = get_upper_bytes(value);
-    self.registers.l = get_lower_bytes(value);
+  fn register_af(&self) -> SixteenBitRegisterAdressingMode {
+    SixteenBitRegisterAdressingMode { register: SixteenBitRegister::AF }
   }
 
-  fn set_af(&mut self, value: u16) {
-    self.registers.a = get_upper_bytes(value);
-    let lower = get_lower_bytes(value);
-    self.flags.z = (lower & 0x80) != 0;
-    self.flags.n = (lower & 0x40) != 0;
-    self.flags.c = (lower & 0x20) != 0;
-    self.flags.h = (lower & 0x10) != 0;
+  fn register_bc(&self) -> SixteenBitRegisterAdressingMode {
+    SixteenBitRegisterAdressingMode { register: SixteenBitRegister::BC }
   }
 
-  // 16-bit register gets
-
-  fn get_bc(&self) -> u16 {
-    let upper = (self.registers.b as u16) << 8;
-    let lower = self.registers.c as u16;
-    return upper | lower;
-  }
-
-  fn get_de(&self) -> u16 {
-    let upper = (self.registers.d as u16) << 8;
-    let lower = self.registers.e as u16;
-    return upper | lower;
+  fn register_de(&self) -> SixteenBitRegisterAdressingMode {
+    SixteenBitRegisterAdressingMode { register: SixteenBitRegister::DE }
   }
 
-  fn get_hl(&self) -> u16 {
-    let upper = (self.registers.h as u16) << 8;
-    let lower = self.registers.l as u16;
-    return upper | lower;
+  fn register_hl(&self) -> SixteenBitRegisterAdressingMode {
+    SixteenBitRegisterAdressingMode { register: SixteenBitRegister::HL }
   }
 
-  fn get_af(&self) -> u16 {
-    let upper = (self.registers.a as u16) << 8;
-    let mut lower = 0u16;
-    if self.flags.z { lower |= 0x80; }
-    if self.flags.n { lower |= 0x40; }
-    if self.flags.c { lower |= 0x20; }
-    if self.flags.h { lower |= 0x10; }
-    return upper | lower;
-  }
   // Loads
 
   fn ld_b<AM:AddressingMode>(&mut self, am: AM) {
@@ -466,44 +641,15 @@ impl CPU {
     }
   }
 
-  fn pop_bc(&mut self) {
-    let value = self.pop_word();
-    self.set_bc(value);
-  }
-
-  fn pop_de(&mut self) {
-    let value = self.pop_word();
-    self.set_de(value);
-  }
-
-  fn pop_hl(&mut self) {
+  fn pop<AM:AddressingMode>(&mut self, am: AM) {
     let value = self.pop_word();
-    self.set_hl(value);
-  }
-
-  fn pop_af(&mut self) {
-    let value = self.pop_word();
-    self.set_af(value);
-  }
-
-  fn push_bc(&mut self) {
-    let value = self.get_bc();
-    self.push_word(value);
-  }
-
-  fn push_de(&mut self) {
-    let value = self.get_de();
-    self.push_word(value);
-  }
-
-  fn push_hl(&mut self) {
-    let value = self.get_hl();
-    self.push_word(value);
+    am.store(self, Data::Word(value));
   }
 
-  fn push_af(&mut self) {
-    let value = self.get_af();
-    self.push_word(value);
+  fn push<AM:AddressingMode>(&mut self, am: AM) {
+    if let Data::Word(value) = am.load(self) {
+      self.push_word(value);
+    }
   }
 
   // Stores
@@ -524,12 +670,13 @@ impl CPU {
     let mut hl = ((self.registers.h as u16) << 8) + self.registers.l as u16;
 
     if (W(hl) + W(value)).0 < hl {
-      self.flags.c = true;
+      self.registers.set_flag(Flag::C, true);
     } else {
-      self.flags.c = false;
+      self.registers.set_flag(Flag::C, false);
     }
 
-    self.flags.n = false;
+    self.registers.set_flag(Flag::N, false);
+    self.registers.set_flag(Flag::H, (hl & 0xfff) + (value & 0xfff) > 0xfff);
 
     hl = (W(hl) + W(value)).0;
 
@@ -543,57 +690,64 @@ impl CPU {
       Data::Byte(byte) => {
         let result = (W(self.registers.a as u16) + W(byte as u16)).0;
 
-        self.flags.z = (result & 0xff) == 0;
-        self.flags.n = false;
-        self.flags.h = ((W(self.registers.a & 0x0f) + W(byte & 0x0f)).0 & 0x10) == 0x10;
-        self.flags.c = result > 0xff;
+        self.registers.set_flag(Flag::Z, (result & 0xff) == 0);
+        self.registers.set_flag(Flag::N, false);
+        self.registers.set_flag(Flag::H, ((W(self.registers.a & 0x0f) + W(byte & 0x0f)).0 & 0x10) == 0x10);
+        self.registers.set_flag(Flag::C, result > 0xff);
         self.registers.a = (result & 0xff) as u8
       },
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn add_sp<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
       Data::SignedByte(byte) => {
-        let result = (W(self.registers.sp as i32) + W(byte as i32)).0 as u32;
-        self.flags.z = false;
-        self.flags.n = false;
-        self.flags.h = (W(self.registers.sp & 0x0fff) + W(byte as u16)).0 > 0x0fff;
-        self.flags.c = result > 0xffff;
-        self.registers.sp = (result & 0xffff) as u16;
+        self.registers.sp = self.sp_plus_signed(byte);
       }
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
+  /// SP plus a signed offset, for ADD SP,r8 and LD HL,SP+r8. H and C come
+  /// from adding the offset's unsigned byte to the low byte of SP.
+  fn sp_plus_signed(&mut self, offset: i8) -> u16 {
+    let sp = self.registers.sp;
+    let byte = offset as u8 as u16;
+    self.registers.set_flag(Flag::Z, false);
+    self.registers.set_flag(Flag::N, false);
+    self.registers.set_flag(Flag::H, (sp & 0x0f) + (byte & 0x0f) > 0x0f);
+    self.registers.set_flag(Flag::C, (sp & 0xff) + byte > 0xff);
+    sp.wrapping_add(offset as i16 as u16)
+  }
+
   fn adc_a<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
       Data::Byte(byte) => {
         let mut result = self.registers.a as u16 + byte as u16;
-        if self.flags.c {
-          result = result + 1;
+        if self.registers.flag(Flag::C) {
+          result += 1;
         }
-        self.flags.z = (result & 0xff) == 0;
-        self.flags.n = false;
-        self.flags.h = ((self.registers.a & 0x0f) + (byte & 0x0f)) & 0x10 == 0x10;
-        self.flags.c = result > 0xff;
+        self.registers.set_flag(Flag::Z, (result & 0xff) == 0);
+        self.registers.set_flag(Flag::N, false);
+        self.registers.set_flag(Flag::H, ((self.registers.a & 0x0f) + (byte & 0x0f)) & 0x10 == 0x10);
+        self.registers.set_flag(Flag::C, result > 0xff);
         self.registers.a = (result & 0xff) as u8
       },
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn cp<AM:AddressingMode>(&mut self, am: AM) -> u8 {
     match am.load(self) {
       Data::Byte(byte) => {
-        self.flags.z = self.registers.a == byte;
-        self.flags.n = true;
-        self.flags.h = (self.registers.a & 0xf) < (byte & 0xf);
-        self.flags.c = self.registers.a < byte;
-        return (W(self.registers.a) - W(byte)).0;
+        self.registers.set_flag(Flag::Z, self.registers.a == byte);
+        self.registers.set_flag(Flag::N, true);
+        self.registers.set_flag(Flag::H, (self.registers.a & 0xf) < (byte & 0xf));
+        self.registers.set_flag(Flag::C, self.registers.a < byte);
+        (W(self.registers.a) - W(byte)).0
       }
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
@@ -604,13 +758,16 @@ impl CPU {
   fn sbc_a<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
       Data::Byte(byte) => {
-        let byte = byte - 1;
-        self.flags.z = self.registers.a == byte;
-        self.flags.n = true;
-        self.flags.h = (self.registers.a & 0xf) < (byte & 0xf);
-        self.flags.c = self.registers.a < byte;
+        let a = self.registers.a;
+        let carry = self.registers.flag(Flag::C) as u8;
+        let result = a.wrapping_sub(byte).wrapping_sub(carry);
+        self.registers.set_flag(Flag::Z, result == 0);
+        self.registers.set_flag(Flag::N, true);
+        self.registers.set_flag(Flag::H, (a & 0xf) < (byte & 0xf) + carry);
+        self.registers.set_flag(Flag::C, (a as u16) < byte as u16 + carry as u16);
+        self.registers.a = result;
       }
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
@@ -618,12 +775,12 @@ impl CPU {
     match am.load(self) {
       Data::Byte(byte) => {
         self.registers.a &= byte;
-        self.flags.z = self.registers.a == 0;
-        self.flags.n = false;
-        self.flags.h = true;
-        self.flags.c = false;
+        self.registers.set_flag(Flag::Z, self.registers.a == 0);
+        self.registers.set_flag(Flag::N, false);
+        self.registers.set_flag(Flag::H, true);
+        self.registers.set_flag(Flag::C, false);
       }
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
@@ -631,12 +788,12 @@ impl CPU {
     match am.load(self) {
       Data::Byte(byte) => {
         self.registers.a ^= byte;
-        self.flags.z = self.registers.a == 0;
-        self.flags.n = false;
-        self.flags.h = false;
-        self.flags.c = false;
+        self.registers.set_flag(Flag::Z, self.registers.a == 0);
+        self.registers.set_flag(Flag::N, false);
+        self.registers.set_flag(Flag::H, false);
+        self.registers.set_flag(Flag::C, false);
       }
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
@@ -644,46 +801,46 @@ impl CPU {
     match am.load(self) {
       Data::Byte(byte) => {
         self.registers.a |= byte;
-        self.flags.z = self.registers.a == 0;
-        self.flags.n = false;
-        self.flags.h = false;
-        self.flags.c = false;
+        self.registers.set_flag(Flag::Z, self.registers.a == 0);
+        self.registers.set_flag(Flag::N, false);
+        self.registers.set_flag(Flag::H, false);
+        self.registers.set_flag(Flag::C, false);
       }
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn inc<AM:AddressingMode>(&mut self, am: AM) {
       match am.load(self) {
           Data::Byte(mut b) => {
-              // Carry 3rd bit?
-              self.flags.h = (b & 0x7) == 0x7;
+              // Carry from bit 3?
+              self.registers.set_flag(Flag::H, (b & 0xf) == 0xf);
 
               b = (W(b) + W(1)).0;
 
-              self.flags.z = b == 0;
-              self.flags.n = false;
+              self.registers.set_flag(Flag::Z, b == 0);
+              self.registers.set_flag(Flag::N, false);
 
               am.store(self, Data::Byte(b));
           }
-          _ => panic!("Unexpected addressing mode")
+          _ => unreachable!("Unexpected addressing mode")
       }
   }
 
   fn dec<AM:AddressingMode>(&mut self, am: AM) {
       match am.load(self) {
           Data::Byte(mut b) => {
-              // Borrow from 4th bit?
-              self.flags.h = (b & 0x8) == 0x8;
+              // Borrow into bit 3?
+              self.registers.set_flag(Flag::H, (b & 0xf) == 0);
 
               b = (W(b) - W(1)).0;
 
-              self.flags.z = b == 0;
-              self.flags.n = true;
+              self.registers.set_flag(Flag::Z, b == 0);
+              self.registers.set_flag(Flag::N, true);
 
               am.store(self, Data::Byte(b));
           },
-          _ => panic!("Unexpected addressing mode")
+          _ => unreachable!("Unexpected addressing mode")
       }
   }
 
@@ -695,24 +852,23 @@ impl CPU {
 
   fn ld_bc<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
-      Data::Word(word) => self.set_bc(word),
-      _ => panic!("Unexpected addressing mode")
+      Data::Word(word) => self.registers.set_bc(word),
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn ld_de<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
-      Data::Word(word) => self.set_de(word),
-      _ => panic!("Unexpected addressing mode")
+      Data::Word(word) => self.registers.set_de(word),
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn ld_hl<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
-      Data::Word(word) => self.set_hl(word),
-      _ => panic!("Unexpected addressing mode")
+      Data::Word(word) => self.registers.set_hl(word),
+      _ => unreachable!("Unexpected addressing mode")
     }
-    self.m += 12
   }
 
   fn ld_sp<AM:AddressingMode>(&mut self, am: AM) {
@@ -720,14 +876,12 @@ impl CPU {
       Data::Word(word) => self.registers.sp = word,
       _ => {}
     }
-    self.m += 12
   }
 
   fn ld_hl_sp_plus_immediate_signed(&mut self) {
-    let  sp = self.registers.sp as i16;
-    let immediate = self.take_byte() as i16;
-
-    self.set_hl((W(sp) + W(immediate)).0 as u16);
+    let offset = self.take_byte() as i8;
+    let result = self.sp_plus_signed(offset);
+    self.registers.set_hl(result);
   }
 
   fn ldh_a<AM:AddressingMode>(&mut self, am: AM) {
@@ -737,10 +891,10 @@ impl CPU {
         let mem = self.address(address);
         match mem.load(self) {
           Data::Byte(val) => self.registers.a = val,
-          _ => panic!("Unexpected addressing mode")
+          _ => unreachable!("Unexpected addressing mode")
         }
       }
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
     self.m  += 12
   }
@@ -846,78 +1000,87 @@ impl CPU {
 
   // Rotations
 
-  fn rl<AM: AddressingMode>(&mut self, am: AM) {
+  /// Stores the result of a CB-prefixed rotate or shift. `op` maps the operand
+  /// to the result and the new carry; z is set from the result and n and h are
+  /// always cleared.
+  fn rotate<AM: AddressingMode, F: Fn(u8) -> (u8, bool)>(&mut self, am: AM, op: F) {
     match am.load(self) {
-      Data::Byte(mut b) => {
-        let old_c = self.flags.c;
-        self.flags.c = b & 0x80 == 0x80;
-
-        b = (b << 1) | (if old_c { 1 } else { 0 }); // Rotate value left
+      Data::Byte(b) => {
+        let (result, carry) = op(b);
 
-        am.store(self, Data::Byte(b));
+        am.store(self, Data::Byte(result));
 
-        self.flags.z = b == 0;
-        self.flags.n = false;
-        self.flags.h = false;
+        self.registers.set_flag(Flag::Z, result == 0);
+        self.registers.set_flag(Flag::N, false);
+        self.registers.set_flag(Flag::H, false);
+        self.registers.set_flag(Flag::C, carry);
+        self.m += am.access_cycles() * 2;
       }
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
+  fn rl<AM: AddressingMode>(&mut self, am: AM) {
+    let old_c = if self.registers.flag(Flag::C) { 1 } else { 0 };
+    self.rotate(am, |b| ((b << 1) | old_c, b & 0x80 == 0x80));
+  }
+
   fn rlc<AM: AddressingMode>(&mut self, am: AM) {
-    match am.load(self) {
-      Data::Byte(mut b) => {
-        self.flags.c = b & 0x80 == 0x80;
+    self.rotate(am, |b| (b.rotate_left(1), b & 0x80 == 0x80));
+  }
 
-        b = (b << 1) | (b >> 7); // Rotate value left
+  fn rr<AM: AddressingMode>(&mut self, am: AM) {
+    let old_c = if self.registers.flag(Flag::C) { 0x80 } else { 0 };
+    self.rotate(am, |b| ((b >> 1) | old_c, b & 1 == 1));
+  }
 
-        am.store(self, Data::Byte(b));
+  fn rrc<AM: AddressingMode>(&mut self, am: AM) {
+    self.rotate(am, |b| (b.rotate_right(1), b & 1 == 1));
+  }
 
-        self.flags.z = b == 0;
-        self.flags.n = false;
-        self.flags.h = false;
-      }
-      _ => panic!("Unexpected addressing mode")
-    }
+  fn sla<AM: AddressingMode>(&mut self, am: AM) {
+    self.rotate(am, |b| (b << 1, b & 0x80 == 0x80));
+  }
+
+  /// SRA keeps the sign bit in place
+  fn sra<AM: AddressingMode>(&mut self, am: AM) {
+    self.rotate(am, |b| ((b >> 1) | (b & 0x80), b & 1 == 1));
+  }
+
+  fn srl<AM: AddressingMode>(&mut self, am: AM) {
+    self.rotate(am, |b| (b >> 1, b & 1 == 1));
+  }
+
+  fn swap<AM: AddressingMode>(&mut self, am: AM) {
+    self.rotate(am, |b| (b.rotate_left(4), false));
   }
 
   /// RLCA differs from RLC A in that it always clears the z flag
   fn rlca(&mut self) {
     let am = self.register_a();
     self.rlc(am);
-    self.flags.z = false;
+    self.registers.set_flag(Flag::Z, false);
   }
 
-  /// CLA differs from RL A in that it always clears the z flag
+  /// RLA differs from RL A in that it always clears the z flag
   fn rla(&mut self) {
     let am = self.register_a();
     self.rl(am);
-    self.flags.z = false;
+    self.registers.set_flag(Flag::Z, false);
   }
 
+  /// RRCA differs from RRC A in that it always clears the z flag
   fn rrca(&mut self) {
-    if (self.registers.a & 1) == 1 {
-      self.flags.c = true;
-    } else {
-      self.flags.c = false;
-    }
-    self.registers.a = (self.registers.a >> 1) | (self.registers.a << 7);
+    let am = self.register_a();
+    self.rrc(am);
+    self.registers.set_flag(Flag::Z, false);
   }
 
+  /// RRA differs from RR A in that it always clears the z flag
   fn rra(&mut self) {
-    let old_f: u8;
-    if self.flags.c {
-      old_f = 0x80;
-    } else {
-      old_f = 0;
-    }
-    if (self.registers.a & 1) == 1 {
-      self.flags.c = true;
-    } else {
-      self.flags.c = false;
-    }
-
-    self.registers.a = (self.registers.a >> 1) | old_f;
+    let am = self.register_a();
+    self.rr(am);
+    self.registers.set_flag(Flag::Z, false);
   }
 
   // Jumps
@@ -927,7 +1090,7 @@ impl CPU {
       Data::SignedByte(byte) => {
         self.registers.pc = (W(self.registers.pc as i16) + W(byte as i16)).0 as u16;
       },
-      _ => panic!()
+      _ => unreachable!("Unexpected addressing mode")
     }
     self.m += 4;
   }
@@ -935,100 +1098,96 @@ impl CPU {
   fn jr_nz<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
       Data::SignedByte(byte) => {
-        if !self.flags.z {
+        if !self.registers.flag(Flag::Z) {
           self.registers.pc = (W(self.registers.pc as i16) + W(byte as i16)).0 as u16;
-          self.m += 12
-        } else {
-          self.m += 8;
+          // Fetching the opcode and offset is already counted
+          self.m += 4;
         }
       },
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn jr_z<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
       Data::SignedByte(byte) => {
-        if self.flags.z {
+        if self.registers.flag(Flag::Z) {
           self.registers.pc = (W(self.registers.pc as i16) + W(byte as i16)).0 as u16;
-          self.m += 12;
-        } else {
-          self.m += 8;
+          // Fetching the opcode and offset is already counted
+          self.m += 4;
         }
       },
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn jr_nc<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
       Data::SignedByte(byte) => {
-        if !self.flags.c {
+        if !self.registers.flag(Flag::C) {
           self.registers.pc = (W(self.registers.pc as i16) + W(byte as i16)).0 as u16;
-          self.m += 12;
-        } else {
-          self.m += 8;
+          // Fetching the opcode and offset is already counted
+          self.m += 4;
         }
       },
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn jr_c<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
       Data::SignedByte(byte) => {
-        if self.flags.c {
+        if self.registers.flag(Flag::C) {
           self.registers.pc = (W(self.registers.pc as i16) + W(byte as i16)).0 as u16;
-          self.m += 12;
-        } else {
-          self.m += 8;
+          // Fetching the opcode and offset is already counted
+          self.m += 4;
         }
       },
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn jp_nz<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
       Data::Word(word) => {
-        if !self.flags.z {
+        if !self.registers.flag(Flag::Z) {
           self.registers.pc = word;
         }
       },
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn jp_z<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
       Data::Word(word) => {
-        if self.flags.z {
+        if self.registers.flag(Flag::Z) {
           self.registers.pc = word;
         }
       },
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn jp_nc<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
       Data::Word(word) => {
-        if !self.flags.c {
+        if !self.registers.flag(Flag::C) {
           self.registers.pc = word;
         }
       },
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn jp_c<AM:AddressingMode>(&mut self, am: AM) {
     match am.load(self) {
       Data::Word(word) => {
-        if self.flags.c {
+        if self.registers.flag(Flag::C) {
           self.registers.pc = word;
         }
       },
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
@@ -1037,30 +1196,30 @@ impl CPU {
       Data::Word(word) => {
         self.registers.pc = word;
       }
-      _ => panic!("Unexpected addressing mode")
+      _ => unreachable!("Unexpected addressing mode")
     }
   }
 
   fn ret_nz(&mut self) {
-    if !self.flags.z {
+    if !self.registers.flag(Flag::Z) {
       self.registers.pc = self.pop_word();
     }
   }
 
   fn ret_z(&mut self) {
-    if self.flags.z {
+    if self.registers.flag(Flag::Z) {
       self.registers.pc = self.pop_word();
     }
   }
 
   fn ret_nc(&mut self) {
-    if !self.flags.c {
+    if !self.registers.flag(Flag::C) {
       self.registers.pc = self.pop_word();
     }
   }
 
   fn ret_c(&mut self) {
-    if self.flags.c {
+    if self.registers.flag(Flag::C) {
       self.registers.pc = self.pop_word();
     }
   }
@@ -1075,57 +1234,43 @@ impl CPU {
   }
 
   fn call_nz<AM:AddressingMode>(&mut self, am: AM) {
-    if !self.flags.z {
-      let val = self.take_word();
-      self.push_word(val);
-      self.registers.pc = match am.load(self) {
-        Data::Word(w) => w,
-        _ => panic!("Unexpected addressing mode!")
-      }
-    }
+    let condition = !self.registers.flag(Flag::Z);
+    self.call_if(condition, am);
   }
 
   fn call_z<AM:AddressingMode>(&mut self, am: AM) {
-    if self.flags.z {
-      let val = self.take_word();
-      self.push_word(val);
-      self.registers.pc = match am.load(self) {
-        Data::Word(w) => w,
-        _ => panic!("Unexpected addressing mode!")
-      }
-    }
+    let condition = self.registers.flag(Flag::Z);
+    self.call_if(condition, am);
   }
 
   fn call_nc<AM:AddressingMode>(&mut self, am: AM) {
-    if !self.flags.c {
-      let val = self.take_word();
-      self.push_word(val);
-      self.registers.pc = match am.load(self) {
-        Data::Word(w) => w,
-        _ => panic!("Unexpected addressing mode!")
-      }
-    }
+    let condition = !self.registers.flag(Flag::C);
+    self.call_if(condition, am);
   }
 
   fn call_c<AM:AddressingMode>(&mut self, am: AM) {
-    if self.flags.c {
-      let val = self.take_word();
-      self.push_word(val);
-      self.registers.pc = match am.load(self) {
-        Data::Word(w) => w,
-        _ => panic!("Unexpected addressing mode!")
-      }
-    }
+    let condition = self.registers.flag(Flag::C);
+    self.call_if(condition, am);
   }
 
   fn call<AM:AddressingMode>(&mut self, am: AM) {
+    self.call_if(true, am);
+  }
+
+  /// Reads the target whether or not the call is taken, then pushes the
+  /// address of the next instruction and jumps if `condition` holds
+  fn call_if<AM:AddressingMode>(&mut self, condition: bool, am: AM) {
     match am.load(self) {
       Data::Word(addr) => {
-        let pc = self.registers.pc;
-        self.push_word(pc);
-        self.registers.pc = addr;
+        if condition {
+          let pc = self.registers.pc;
+          self.push_word(pc);
+          self.registers.pc = addr;
+          // An internal delay and the push, the fetches are already counted
+          self.m += 12;
+        }
       }
-      _ => panic!("Unexpected addressing mode!")
+      _ => unreachable!("Unexpected addressing mode")
     };
   }
 
@@ -1170,56 +1315,89 @@ impl CPU {
 
   fn stop(&mut self) {
     self.stopped = true;
-    self.registers.pc = self.registers.pc + 1;
+    self.registers.pc = self.registers.pc.wrapping_add(1);
+    self.mmu.write_byte(0xff04, 0);
   }
 
+  /// Enters low-power mode until an interrupt is pending. If IME is clear and
+  /// an interrupt is already pending the CPU doesn't halt at all; instead the
+  /// HALT bug causes the following byte to be read twice.
   fn halt(&mut self) {
-      // TODO: HALT
-      panic!("Halt");
+    // IME is set by the time a HALT straight after EI takes effect
+    let ime = self.interrupts || self.ime_delay > 0;
+    if !ime && self.pending_interrupts() != 0 {
+      self.halt_bug = true;
+    } else {
+      self.halted = true;
+    }
+  }
+
+  /// The 11 unused opcodes hang the CPU until it's reset
+  fn illegal(&mut self, op: u8) {
+    let address = (W(self.registers.pc) - W(1)).0;
+    self.locked = Some((op, address));
   }
 
   fn disable_interrupts(&mut self) {
       self.interrupts = false;
+      self.ime_delay = 0;
   }
 
   fn enable_interrupts(&mut self) {
-      self.interrupts = true;
+      if !self.interrupts {
+        self.ime_delay = 2;
+      }
   }
 
   // Miscellaneous
 
+  /// Corrects A to binary coded decimal after adding or subtracting two BCD
+  /// numbers, using N, H and C to tell what the last operation was
   fn daa(&mut self) {
-    self.flags.c = false;
-    if (self.registers.a & 0x0f) > 9 {
-      self.registers.a = self.registers.a + 0x06;
-    }
-
-    if ((self.registers.a & 0xf0) >> 4) > 9 {
-      self.flags.c = true;
-      self.registers.a = self.registers.a + 0x60;
+    let mut a = self.registers.a;
+    let mut carry = self.registers.flag(Flag::C);
+    if !self.registers.flag(Flag::N) {
+      if carry || a > 0x99 {
+        a = a.wrapping_add(0x60);
+        carry = true;
+      }
+      if self.registers.flag(Flag::H) || (a & 0x0f) > 0x09 {
+        a = a.wrapping_add(0x06);
+      }
+    } else {
+      if carry {
+        a = a.wrapping_sub(0x60);
+      }
+      if self.registers.flag(Flag::H) {
+        a = a.wrapping_sub(0x06);
+      }
     }
 
-    self.flags.h = false;
-    self.flags.z = self.registers.a == 0;
+    self.registers.a = a;
+    self.registers.set_flag(Flag::Z, a == 0);
+    self.registers.set_flag(Flag::H, false);
+    self.registers.set_flag(Flag::C, carry);
     self.m += 4;
   }
 
   fn cpl(&mut self) {
     self.registers.a = !self.registers.a;
+    self.registers.set_flag(Flag::N, true);
+    self.registers.set_flag(Flag::H, true);
     self.m += 4;
   }
 
   fn scf(&mut self) {
-    self.flags.n = false;
-    self.flags.h = false;
-    self.flags.c = true;
+    self.registers.set_flag(Flag::N, false);
+    self.registers.set_flag(Flag::H, false);
+    self.registers.set_flag(Flag::C, true);
     self.m += 4;
   }
 
   fn ccf(&mut self) {
-    self.flags.n = false;
-    self.flags.h = false;
-    self.flags.c = !self.flags.c;
+    self.registers.set_flag(Flag::N, false);
+    self.registers.set_flag(Flag::H, false);
+    self.registers.set_flag(Flag::C, !self.registers.flag(Flag::C));
     self.m += 4;
   }
 
@@ -1229,38 +1407,177 @@ impl CPU {
       match am.load(self) {
           Data::Byte(value) => {
               let check = 1 << bit;
-              self.flags.z = value & check == 0;
-              self.flags.n = false;
-              self.flags.h = true;
+              self.registers.set_flag(Flag::Z, value & check == 0);
+              self.registers.set_flag(Flag::N, false);
+              self.registers.set_flag(Flag::H, true);
+          }
+          _ => unreachable!("Unexpected addressing mode")
+      }
+      self.m += am.access_cycles();
+  }
+
+  fn res<AM:AddressingMode>(&mut self, bit: u8, am: AM) {
+      match am.load(self) {
+          Data::Byte(value) => {
+              am.store(self, Data::Byte(value & !(1 << bit)));
           }
-          _ => panic!("Unexpected addressing mode")
+          _ => unreachable!("Unexpected addressing mode")
       }
-      self.m += 8;
+      self.m += am.access_cycles() * 2;
+  }
+
+  fn set<AM:AddressingMode>(&mut self, bit: u8, am: AM) {
+      match am.load(self) {
+          Data::Byte(value) => {
+              am.store(self, Data::Byte(value | (1 << bit)));
+          }
+          _ => unreachable!("Unexpected addressing mode")
+      }
+      self.m += am.access_cycles() * 2;
   }
 }
 
-fn get_lower_bytes(word: u16) -> u8 {
-  (word & 0xff) as u8
+impl CPU<MMU> {
+  /// Starts the cartridge at 0x0100 as if `model`'s boot ROM had just run
+  pub fn skip_boot(&mut self, model: Model) {
+    self.mmu.skip_boot(model);
+    let registers = model.post_boot(self.mmu.read_byte(0x014d));
+    self.registers.set_af(registers.af);
+    self.registers.set_bc(registers.bc);
+    self.registers.set_de(registers.de);
+    self.registers.set_hl(registers.hl);
+    self.registers.sp = registers.sp;
+    self.registers.pc = 0x0100;
+  }
+
+  /// Captures the whole machine as a versioned blob that `load_state` can
+  /// restore. States are tied to the ROM they were made with.
+  pub fn save_state(&self) -> Vec<u8> {
+    let mut writer = StateWriter::new();
+    writer.bytes(state::MAGIC);
+    writer.u32(state::VERSION);
+    writer.u32(self.mmu.cartridge.checksum());
+    self.write_state(&mut writer);
+    writer.into_inner()
+  }
+
+  /// Restores a blob from `save_state`. States for another ROM or from a
+  /// build with a different layout are rejected, and a corrupt state leaves
+  /// the machine as it was.
+  pub fn load_state(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+    let mut reader = StateReader::new(data);
+    let mut magic = [0; 4];
+    reader.bytes(&mut magic)?;
+    if &magic != state::MAGIC {
+      return Err(EmulatorError::CorruptState);
+    }
+    let version = reader.u32()?;
+    if version != state::VERSION {
+      return Err(EmulatorError::StateVersion { found: version, expected: state::VERSION });
+    }
+    if reader.u32()? != self.mmu.cartridge.checksum() {
+      return Err(EmulatorError::StateRomMismatch);
+    }
+
+    let mut backup = StateWriter::new();
+    self.write_state(&mut backup);
+    let result = self.read_state(&mut reader).and_then(|_| {
+      if reader.is_empty() { Ok(()) } else { Err(EmulatorError::CorruptState) }
+    });
+    if result.is_err() {
+      self.read_state(&mut StateReader::new(&backup.into_inner()))?;
+    }
+    result
+  }
 }
-fn get_upper_bytes(word: u16) -> u8 {
-  ((word & 0xff00) >> 8) as u8
+
+impl<B: Bus + Snapshot> Snapshot for CPU<B> {
+  fn write_state(&self, writer: &mut StateWriter) {
+    let r = &self.registers;
+    writer.bytes(&[r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l]);
+    writer.u16(r.sp);
+    writer.u16(r.pc);
+    writer.u64(self.clock.m);
+    writer.u16(self.clock.t);
+    writer.u8(self.m);
+    writer.bool(self.interrupts);
+    writer.u8(self.ime_delay);
+    writer.bool(self.halted);
+    writer.bool(self.halt_bug);
+    writer.bool(self.stopped);
+    let (locked, opcode, address) = match self.locked {
+      Some((opcode, address)) => (true, opcode, address),
+      None => (false, 0, 0)
+    };
+    writer.bool(locked);
+    writer.u8(opcode);
+    writer.u16(address);
+    self.mmu.write_state(writer);
+  }
+
+  fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+    let mut registers = [0; 8];
+    reader.bytes(&mut registers)?;
+    self.registers.a = registers[0];
+    self.registers.set_f(registers[1]);
+    self.registers.b = registers[2];
+    self.registers.c = registers[3];
+    self.registers.d = registers[4];
+    self.registers.e = registers[5];
+    self.registers.h = registers[6];
+    self.registers.l = registers[7];
+    self.registers.sp = reader.u16()?;
+    self.registers.pc = reader.u16()?;
+    self.clock.m = reader.u64()?;
+    self.clock.t = reader.u16()?;
+    self.m = reader.u8()?;
+    self.interrupts = reader.bool()?;
+    self.ime_delay = reader.u8()?;
+    self.halted = reader.bool()?;
+    self.halt_bug = reader.bool()?;
+    self.stopped = reader.bool()?;
+    let locked = reader.bool()?;
+    let opcode = reader.u8()?;
+    let address = reader.u16()?;
+    self.locked = if locked { Some((opcode, address)) } else { None };
+    self.mmu.read_state(reader)
+  }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
+    use boot::Model;
+    use bus::{Bus, FlatBus};
     use cartridge::Cartridge;
+    use data::Data;
+    use debugger::{Debugger, NoDebugger};
+    use error::EmulatorError;
+    use joypad::Button;
+    use memory_map::{ReadByte, WriteByte};
     use mmu::MMU;
-    use super::CPU;
+    use rewind::Rewind;
+    use super::{AddressingMode, CPU, Flag, Status};
+
+    /// Cycles taken by the first instruction of `program`, run from 0x0000
+    fn cycles_for<F: Fn(&mut CPU<FlatBus>)>(program: &[u8], setup: F) -> u64 {
+        let mut bus = FlatBus::new();
+        for (address, &byte) in program.iter().enumerate() {
+            bus.write_byte(address as u16, byte);
+        }
+        let mut cpu = CPU::new(bus);
+        setup(&mut cpu);
+        cpu.step(&mut NoDebugger).unwrap();
+        cpu.clock.m
+    }
 
     macro_rules! assert_cyles_equal {
-        ([$($program:expr),*], $cycles:expr) => {{
-            let cart = Cartridge::new(vec![$($program),*].into_boxed_slice());
-            let mut mmu: MMU = MMU::new();
-            mmu.load_cartridge(cart);
-            let mut cpu: CPU = CPU::new(mmu);
-            cpu.step();
-            assert_eq!(cpu.clock.m, $cycles);
-        }}
+        ([$($program:expr),*], $cycles:expr) => {
+            assert_eq!(cycles_for(&[$($program),*], |_| {}), $cycles)
+        };
+        ([$($program:expr),*], $cycles:expr, $setup:expr) => {
+            assert_eq!(cycles_for(&[$($program),*], $setup), $cycles)
+        };
     }
 
     #[test]
@@ -1299,12 +1616,668 @@ mod tests {
         assert_cyles_equal!([0x1e], 8);              // 0x1e LD E,d8
         assert_cyles_equal!([0x1f], 4);              // 0x1f RRA
 
-        assert_cyles_equal!([0x20, 0x00], 8);        // JR NZ,r8
-        assert_cyles_equal!([0x20, 0x00], 12);       // JR NZ,r8
-        assert_cyles_equal!([0x21], 8);              // LD HL,d16
+        assert_cyles_equal!([0x20, 0x00], 8, |cpu: &mut CPU<FlatBus>| cpu.registers.set_flag(Flag::Z, true)); // JR NZ,r8 not taken
+        assert_cyles_equal!([0x20, 0x00], 12);       // JR NZ,r8 taken
+        assert_cyles_equal!([0x21], 12);             // LD HL,d16
         assert_cyles_equal!([0x22], 8);              // 0x22 ld (hl+) a
         assert_cyles_equal!([0x23], 8);              // 0x23 inc hl
         assert_cyles_equal!([0x32], 8);              // 0x32 ld (hl-) a
         assert_cyles_equal!([0x33], 8);              // 0x33 inc sp
+
+        assert_cyles_equal!([0xc4, 0x00, 0x00], 12, |cpu: &mut CPU<FlatBus>| cpu.registers.set_flag(Flag::Z, true)); // CALL NZ,a16 not taken
+        assert_cyles_equal!([0xc4, 0x00, 0x00], 24); // CALL NZ,a16 taken
+        assert_cyles_equal!([0xcd, 0x00, 0x00], 24); // CALL a16
+    }
+
+    /// Plain memory that logs every access
+    struct RecordingBus {
+        ram: FlatBus,
+        reads: RefCell<Vec<u16>>,
+        writes: Vec<(u16, u8)>,
+        ticks: Vec<u8>
+    }
+
+    impl ReadByte for RecordingBus {
+        fn read_byte(&self, address: u16) -> u8 {
+            self.reads.borrow_mut().push(address);
+            self.ram.read_byte(address)
+        }
+    }
+
+    impl WriteByte for RecordingBus {
+        fn write_byte(&mut self, address: u16, value: u8) {
+            self.writes.push((address, value));
+            self.ram.write_byte(address, value);
+        }
+    }
+
+    impl Bus for RecordingBus {
+        fn tick(&mut self, cycles: u8) {
+            self.ticks.push(cycles);
+        }
+    }
+
+    #[test]
+    fn runs_against_any_bus() {
+        let mut ram = FlatBus::new();
+        for (address, &byte) in [0x01, 0x00, 0xc0, 0x02].iter().enumerate() {
+            ram.write_byte(address as u16, byte);
+        }
+        let bus = RecordingBus { ram, reads: RefCell::new(vec![]), writes: vec![], ticks: vec![] };
+        let mut cpu = CPU::new(bus);
+        cpu.registers.a = 0x42;
+        cpu.step(&mut NoDebugger).unwrap(); // LD BC,$c000
+        cpu.step(&mut NoDebugger).unwrap(); // LD (BC),A
+
+        // IE and IF are read before each instruction to look for interrupts
+        let reads: Vec<_> = cpu.mmu.reads.borrow().iter().cloned().filter(|&a| a < 0xff00).collect();
+        assert_eq!(reads, vec![0, 1, 2, 3]);
+        assert_eq!(cpu.mmu.writes, vec![(0xc000, 0x42)]);
+        assert_eq!(cpu.mmu.ticks, vec![12, 8]);
+    }
+
+    fn program_cpu(program: &[u8]) -> CPU {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0150..0x0150 + program.len()].copy_from_slice(program);
+        let cart = Cartridge::new(rom.into_boxed_slice()).unwrap();
+        let mut mmu: MMU = MMU::new();
+        mmu.load_cartridge(cart);
+        mmu.write_byte(0xff50, 1);
+        let mut cpu: CPU = CPU::new(mmu);
+        cpu.registers.pc = 0x0150;
+        cpu.registers.sp = 0xfffe;
+        cpu
+    }
+
+    #[test]
+    fn prefixed_shifts_and_rotates() {
+        let mut cpu = program_cpu(&[
+            0xcb, 0x37, // SWAP A
+            0xcb, 0x28, // SRA B
+            0xcb, 0x19, // RR C
+            0xcb, 0x02  // RLC D
+        ]);
+        let mut debugger = Debugger::new();
+        cpu.registers.a = 0xf1;
+        cpu.registers.b = 0x81;
+        cpu.registers.c = 0x01;
+        cpu.registers.d = 0x80;
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.a, 0x1f);
+        assert!(!cpu.registers.flag(Flag::C));
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.b, 0xc0);
+        assert!(cpu.registers.flag(Flag::C));
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.c, 0x80);
+        assert!(cpu.registers.flag(Flag::C));
+        assert!(!cpu.registers.flag(Flag::Z));
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.d, 0x01);
+        assert!(cpu.registers.flag(Flag::C));
+        assert_eq!(cpu.clock.m, 32);
+    }
+
+    #[test]
+    fn af_masks_lower_nibble_of_f() {
+        let mut cpu = program_cpu(&[
+            0xf1, // POP AF
+            0xf5  // PUSH AF
+        ]);
+        let mut debugger = Debugger::new();
+        cpu.registers.sp = 0xc000;
+        cpu.mmu.write_byte(0xc000, 0x2f);
+        cpu.mmu.write_byte(0xc001, 0x12);
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.af(), 0x1220);
+        assert!(cpu.registers.flag(Flag::H));
+        assert!(!cpu.registers.flag(Flag::Z) && !cpu.registers.flag(Flag::N) && !cpu.registers.flag(Flag::C));
+
+        cpu.registers.set_flag(Flag::C, true);
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.mmu.read_byte(0xc000), 0x30);
+        assert_eq!(cpu.mmu.read_byte(0xc001), 0x12);
+    }
+
+    #[test]
+    fn stores_sixteen_bit_registers() {
+        let mut cpu = CPU::new(FlatBus::new());
+        let hl = cpu.register_hl();
+        hl.store(&mut cpu, Data::Word(0xc123));
+        assert_eq!((cpu.registers.h, cpu.registers.l), (0xc1, 0x23));
+        assert_eq!(hl.load(&mut cpu), Data::Word(0xc123));
+
+        cpu.registers.set_bc(0x1234);
+        cpu.registers.set_de(0x5678);
+        cpu.registers.set_af(0x9aff);
+        assert_eq!((cpu.registers.bc(), cpu.registers.de(), cpu.registers.af()), (0x1234, 0x5678, 0x9af0));
+        assert_eq!(cpu.registers.f(), 0xf0);
+    }
+
+    #[test]
+    fn conditional_call_pushes_the_next_instruction() {
+        let mut cpu = program_cpu(&[
+            0xc4, 0x34, 0x12, // CALL NZ,1234
+            0xcc, 0x34, 0x12  // CALL Z,1234
+        ]);
+        let mut debugger = Debugger::new();
+        cpu.registers.set_flag(Flag::Z, true);
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.pc, 0x0153);
+        assert_eq!(cpu.registers.sp, 0xfffe);
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.pc, 0x1234);
+        assert_eq!(cpu.registers.sp, 0xfffc);
+        assert_eq!(cpu.mmu.read_word(0xfffc), 0x0156);
+    }
+
+    #[test]
+    fn inc_and_dec_half_carry_at_the_nibble() {
+        let mut cpu = program_cpu(&[
+            0x04, // INC B
+            0x04, // INC B
+            0x05, // DEC B
+            0x05  // DEC B
+        ]);
+        let mut debugger = Debugger::new();
+        cpu.registers.b = 0x07;
+
+        cpu.step(&mut debugger).unwrap();
+        assert!(!cpu.registers.flag(Flag::H));
+        cpu.registers.b = 0x0f;
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.b, 0x10);
+        assert!(cpu.registers.flag(Flag::H));
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.b, 0x0f);
+        assert!(cpu.registers.flag(Flag::H) && cpu.registers.flag(Flag::N));
+        cpu.registers.b = 0x08;
+        cpu.step(&mut debugger).unwrap();
+        assert!(!cpu.registers.flag(Flag::H));
+    }
+
+    #[test]
+    fn cpl_and_add_hl_set_half_carry() {
+        let mut cpu = program_cpu(&[
+            0x2f, // CPL
+            0x09, // ADD HL,BC
+            0x09  // ADD HL,BC
+        ]);
+        let mut debugger = Debugger::new();
+        cpu.registers.a = 0x35;
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.a, 0xca);
+        assert!(cpu.registers.flag(Flag::N) && cpu.registers.flag(Flag::H));
+
+        cpu.registers.set_hl(0x0fff);
+        cpu.registers.set_bc(0x0001);
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.hl(), 0x1000);
+        assert!(cpu.registers.flag(Flag::H) && !cpu.registers.flag(Flag::C) && !cpu.registers.flag(Flag::N));
+
+        cpu.registers.set_hl(0xf000);
+        cpu.registers.set_bc(0x1000);
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.hl(), 0x0000);
+        assert!(!cpu.registers.flag(Flag::H) && cpu.registers.flag(Flag::C));
+    }
+
+    #[test]
+    fn stop_and_ret_wrap_at_the_top_of_memory() {
+        let mut bus = FlatBus::new();
+        bus.write_byte(0xfffe, 0x10); // STOP
+        bus.write_byte(0x0000, 0xc9); // RET
+        bus.write_byte(0xffff, 0x34);
+        let mut cpu = CPU::new(bus);
+        cpu.registers.pc = 0xfffe;
+        cpu.step(&mut NoDebugger).unwrap();
+        assert_eq!(cpu.registers.pc, 0x0000);
+
+        cpu.stopped = false;
+        cpu.registers.sp = 0xffff;
+        cpu.step(&mut NoDebugger).unwrap();
+        assert_eq!(cpu.registers.sp, 0x0001);
+        assert_eq!(cpu.registers.pc, 0xc934);
+    }
+
+    #[test]
+    fn sbc_subtracts_carry() {
+        let mut cpu = program_cpu(&[0x98, 0x98]); // SBC A,B, SBC A,B
+        let mut debugger = Debugger::new();
+        cpu.registers.a = 0x10;
+        cpu.registers.set_flag(Flag::C, true);
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.a, 0x0f);
+        assert!(cpu.registers.flag(Flag::N) && cpu.registers.flag(Flag::H));
+        assert!(!cpu.registers.flag(Flag::Z) && !cpu.registers.flag(Flag::C));
+
+        cpu.registers.a = 0x00;
+        cpu.registers.set_flag(Flag::C, true);
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.a, 0xff);
+        assert!(cpu.registers.flag(Flag::H) && cpu.registers.flag(Flag::C));
+    }
+
+    #[test]
+    fn daa_adjusts_after_add_and_sub() {
+        let mut cpu = program_cpu(&[
+            0x80, // ADD A,B
+            0x27, // DAA
+            0x90, // SUB B
+            0x27, // DAA
+            0x80, // ADD A,B
+            0x27  // DAA
+        ]);
+        let mut debugger = Debugger::new();
+        cpu.registers.a = 0x45;
+        cpu.registers.b = 0x38;
+
+        cpu.step(&mut debugger).unwrap();
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.a, 0x83);
+        assert!(!cpu.registers.flag(Flag::C));
+
+        cpu.step(&mut debugger).unwrap();
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.a, 0x45);
+
+        cpu.registers.a = 0x99;
+        cpu.registers.b = 0x01;
+        cpu.step(&mut debugger).unwrap();
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.a, 0x00);
+        assert!(cpu.registers.flag(Flag::Z) && cpu.registers.flag(Flag::C));
+    }
+
+    #[test]
+    fn sp_plus_signed_flags_come_from_the_low_byte() {
+        let mut cpu = program_cpu(&[
+            0xf8, 0xff, // LD HL,SP-1
+            0xe8, 0x01, // ADD SP,1
+            0xe8, 0x80  // ADD SP,-128
+        ]);
+        let mut debugger = Debugger::new();
+        cpu.registers.sp = 0x0f00;
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.hl(), 0x0eff);
+        assert!(!cpu.registers.flag(Flag::H) && !cpu.registers.flag(Flag::C));
+
+        cpu.registers.sp = 0x00ff;
+        cpu.registers.set_flag(Flag::Z, true);
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.sp, 0x0100);
+        assert!(cpu.registers.flag(Flag::H) && cpu.registers.flag(Flag::C));
+        assert!(!cpu.registers.flag(Flag::Z) && !cpu.registers.flag(Flag::N));
+
+        cpu.registers.sp = 0x0180;
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.sp, 0x0100);
+        assert!(!cpu.registers.flag(Flag::H) && cpu.registers.flag(Flag::C));
+    }
+
+    #[test]
+    fn prefixed_ops_on_memory() {
+        let mut cpu = program_cpu(&[
+            0xcb, 0xfe, // SET 7,(HL)
+            0xcb, 0x86, // RES 0,(HL)
+            0xcb, 0x7e  // BIT 7,(HL)
+        ]);
+        let mut debugger = Debugger::new();
+        cpu.registers.h = 0xc0;
+        cpu.mmu.write_byte(0xc000, 0x01);
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.mmu.read_byte(0xc000), 0x81);
+        assert_eq!(cpu.clock.m, 16);
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.mmu.read_byte(0xc000), 0x80);
+        assert_eq!(cpu.clock.m, 32);
+
+        cpu.step(&mut debugger).unwrap();
+        assert!(!cpu.registers.flag(Flag::Z));
+        assert_eq!(cpu.clock.m, 44);
+    }
+
+    #[test]
+    fn illegal_opcode_locks_cpu() {
+        let mut cpu = program_cpu(&[0xdd, 0x04]); // illegal, INC B
+        let mut debugger = Debugger::new();
+        cpu.interrupts = true;
+        cpu.mmu.ie = 0b0000_0001;
+        assert_eq!(cpu.status(), Status::Running);
+
+        match cpu.step(&mut debugger) {
+            Err(EmulatorError::IllegalOpcode { opcode, address }) => {
+                assert_eq!(opcode, 0xdd);
+                assert_eq!(address, 0x0150);
+            }
+            result => panic!("Expected an illegal opcode error, got {:?}", result)
+        }
+        assert_eq!(cpu.status(), Status::Locked(0xdd));
+
+        cpu.mmu.interrupt_flag = 0b0000_0001;
+        for _ in 0..10 { assert!(cpu.step(&mut debugger).is_err()); }
+        assert_eq!(cpu.status(), Status::Locked(0xdd));
+        assert_eq!(cpu.registers.pc, 0x0151);
+        assert_eq!(cpu.registers.b, 0);
+    }
+
+    #[test]
+    fn interrupt_dispatch_jumps_to_vector() {
+        let mut cpu = program_cpu(&[]);
+        cpu.interrupts = true;
+        cpu.mmu.ie = 0b0000_0100;
+        cpu.mmu.interrupt_flag = 0b0000_0100;
+        cpu.step(&mut Debugger::new()).unwrap();
+
+        assert_eq!(cpu.registers.pc, 0x50);
+        assert_eq!(cpu.registers.sp, 0xfffc);
+        assert_eq!(cpu.mmu.read_word(0xfffc), 0x0150);
+        assert_eq!(cpu.mmu.interrupt_flag, 0);
+        assert!(!cpu.interrupts);
+        assert_eq!(cpu.clock.m, 20);
+    }
+
+    #[test]
+    fn interrupt_dispatch_priority() {
+        let mut cpu = program_cpu(&[]);
+        cpu.interrupts = true;
+        cpu.mmu.ie = 0b0001_1110;
+        cpu.mmu.interrupt_flag = 0b0001_1011;
+        cpu.step(&mut Debugger::new()).unwrap();
+
+        assert_eq!(cpu.registers.pc, 0x48);
+        assert_eq!(cpu.mmu.interrupt_flag, 0b0001_1001);
+    }
+
+    #[test]
+    fn ei_enables_interrupts_after_next_instruction() {
+        let mut cpu = program_cpu(&[0xfb, 0x00, 0x00]); // EI, NOP, NOP
+        let mut debugger = Debugger::new();
+        cpu.mmu.ie = 0b0000_0001;
+        cpu.mmu.interrupt_flag = 0b0000_0001;
+        cpu.step(&mut debugger).unwrap();
+        assert!(!cpu.interrupts);
+
+        cpu.step(&mut debugger).unwrap();
+        assert!(cpu.interrupts);
+        assert_eq!(cpu.registers.pc, 0x0152);
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.pc, 0x40);
+        assert_eq!(cpu.mmu.read_word(0xfffc), 0x0152);
+    }
+
+    #[test]
+    fn di_straight_after_ei_cancels_it() {
+        let mut cpu = program_cpu(&[0xfb, 0xf3, 0x00]); // EI, DI, NOP
+        let mut debugger = Debugger::new();
+        cpu.mmu.ie = 0b0000_0001;
+        cpu.mmu.interrupt_flag = 0b0000_0001;
+        for _ in 0..3 { cpu.step(&mut debugger).unwrap(); }
+        assert!(!cpu.interrupts);
+        assert_eq!(cpu.registers.pc, 0x0153);
+    }
+
+    #[test]
+    fn reti_enables_interrupts_immediately() {
+        let mut cpu = program_cpu(&[0xd9]); // RETI
+        let mut debugger = Debugger::new();
+        cpu.push_word(0x0160);
+        cpu.mmu.ie = 0b0000_0001;
+        cpu.mmu.interrupt_flag = 0b0000_0001;
+        cpu.step(&mut debugger).unwrap();
+        assert!(cpu.interrupts);
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.pc, 0x40);
+        assert_eq!(cpu.mmu.read_word(0xfffc), 0x0160);
+    }
+
+    #[test]
+    fn ei_then_halt_services_interrupt_after_halt() {
+        let mut cpu = program_cpu(&[0xfb, 0x76, 0x00]); // EI, HALT, NOP
+        let mut debugger = Debugger::new();
+        cpu.mmu.ie = 0b0000_0001;
+        cpu.mmu.interrupt_flag = 0b0000_0001;
+        cpu.step(&mut debugger).unwrap();
+        cpu.step(&mut debugger).unwrap();
+        assert!(cpu.interrupts);
+        assert_eq!(cpu.registers.pc, 0x0152);
+
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.pc, 0x40);
+        assert_eq!(cpu.mmu.read_word(0xfffc), 0x0152);
+    }
+
+    #[test]
+    fn halt_idles_until_interrupt_pending() {
+        let mut cpu = program_cpu(&[0x76, 0x00]); // HALT, NOP
+        let mut debugger = Debugger::new();
+        cpu.mmu.ie = 0b0000_0100;
+        cpu.step(&mut debugger).unwrap();
+        assert!(cpu.halted);
+
+        for _ in 0..10 { cpu.step(&mut debugger).unwrap(); }
+        assert!(cpu.halted);
+        assert_eq!(cpu.registers.pc, 0x0151);
+
+        cpu.mmu.interrupt_flag = 0b0000_0100;
+        cpu.step(&mut debugger).unwrap();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.registers.pc, 0x0152);
+    }
+
+    #[test]
+    fn halt_bug_repeats_next_byte() {
+        let mut cpu = program_cpu(&[0x76, 0x04]); // HALT, INC B
+        let mut debugger = Debugger::new();
+        cpu.mmu.ie = 0b0000_0001;
+        cpu.mmu.interrupt_flag = 0b0000_0001;
+        cpu.step(&mut debugger).unwrap();
+        assert!(!cpu.halted);
+
+        cpu.step(&mut debugger).unwrap();
+        cpu.step(&mut debugger).unwrap();
+        assert_eq!(cpu.registers.b, 2);
+        assert_eq!(cpu.registers.pc, 0x0152);
+    }
+
+    #[test]
+    fn stop_waits_for_joypad() {
+        let mut cpu = program_cpu(&[0x10, 0x00]); // STOP
+        let mut debugger = Debugger::new();
+        cpu.step(&mut debugger).unwrap();
+        assert!(cpu.stopped);
+
+        for _ in 0..10 { cpu.step(&mut debugger).unwrap(); }
+        assert!(cpu.stopped);
+        assert_eq!(cpu.registers.pc, 0x0152);
+
+        cpu.mmu.write_byte(0xff00, 0b0001_0000);
+        cpu.mmu.joypad.press(Button::Start);
+        cpu.step(&mut debugger).unwrap();
+        assert!(!cpu.stopped);
+    }
+
+    #[test]
+    fn stopped_cpu_keeps_counting_cycles() {
+        let mut cpu = program_cpu(&[0x10, 0x00]); // STOP
+        let mut debugger = Debugger::new();
+        cpu.step(&mut debugger).unwrap();
+        let cycles = cpu.cycles();
+        for _ in 0..10 { cpu.step(&mut debugger).unwrap(); }
+        assert!(cpu.stopped);
+        assert_eq!(cpu.cycles(), cycles + 40);
+    }
+
+    #[test]
+    fn interrupt_not_dispatched_without_ime() {
+        let mut cpu = program_cpu(&[]);
+        cpu.mmu.ie = 0b0000_0001;
+        cpu.mmu.interrupt_flag = 0b0000_0001;
+        cpu.step(&mut Debugger::new()).unwrap();
+
+        assert_eq!(cpu.registers.pc, 0x0151);
+        assert_eq!(cpu.mmu.read_byte(0xff0f), 0b0000_0001);
+    }
+
+    fn counting_cpu() -> CPU {
+        program_cpu(&[
+            0x3c,             // INC A
+            0xea, 0x00, 0xc0, // LD ($c000),A
+            0x18, 0xfa        // JR -6
+        ])
+    }
+
+    #[test]
+    fn load_state_restores_machine() {
+        let mut cpu = counting_cpu();
+        let mut debugger = Debugger::new();
+        for _ in 0..1000 { cpu.step(&mut debugger).unwrap(); }
+        let state = cpu.save_state();
+
+        for _ in 0..5000 { cpu.step(&mut debugger).unwrap(); }
+        let expected = cpu.save_state();
+
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.save_state(), state);
+        for _ in 0..5000 { cpu.step(&mut debugger).unwrap(); }
+        assert_eq!(cpu.save_state(), expected);
+    }
+
+    #[test]
+    fn rewind_restores_earlier_frames() {
+        let mut cpu = counting_cpu();
+        let mut debugger = Debugger::new();
+        let mut rewind = Rewind::new(10);
+        let mut frames = vec![];
+        while cpu.mmu.gpu.frame_count() <= 5 {
+            rewind.record(&cpu);
+            if frames.len() as u64 == cpu.mmu.gpu.frame_count() {
+                frames.push(cpu.save_state());
+            }
+            cpu.step(&mut debugger).unwrap();
+        }
+
+        cpu.load_state(&rewind.rewind(3).unwrap()).unwrap();
+        assert_eq!(cpu.save_state(), frames[2]);
+        cpu.load_state(&rewind.rewind(2).unwrap()).unwrap();
+        assert_eq!(cpu.save_state(), frames[0]);
+    }
+
+    #[test]
+    fn load_state_rejects_other_versions_and_roms() {
+        let mut cpu = counting_cpu();
+        let mut state = cpu.save_state();
+        state[4] = state[4].wrapping_add(1);
+        match cpu.load_state(&state) {
+            Err(EmulatorError::StateVersion { .. }) => {}
+            result => panic!("Expected a version error, got {:?}", result)
+        }
+
+        let mut state = cpu.save_state();
+        state[8] ^= 0xff;
+        match cpu.load_state(&state) {
+            Err(EmulatorError::StateRomMismatch) => {}
+            result => panic!("Expected a ROM mismatch, got {:?}", result)
+        }
+
+        // The header checksums are the same, but the ROMs aren't
+        let state = program_cpu(&[0x00]).save_state();
+        match program_cpu(&[0x01]).load_state(&state) {
+            Err(EmulatorError::StateRomMismatch) => {}
+            result => panic!("Expected a ROM mismatch, got {:?}", result)
+        }
+
+        match cpu.load_state(b"not a state") {
+            Err(EmulatorError::CorruptState) => {}
+            result => panic!("Expected a corrupt state error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn truncated_state_leaves_machine_untouched() {
+        let mut cpu = counting_cpu();
+        let mut debugger = Debugger::new();
+        let state = cpu.save_state();
+        for _ in 0..100 { cpu.step(&mut debugger).unwrap(); }
+        let before = cpu.save_state();
+
+        match cpu.load_state(&state[..state.len() - 1]) {
+            Err(EmulatorError::CorruptState) => {}
+            result => panic!("Expected a corrupt state error, got {:?}", result)
+        }
+        assert_eq!(cpu.save_state(), before);
+    }
+
+    fn booting_cpu() -> CPU {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0xaa;
+        rom[0x014d] = 0x3c; // Header checksum
+        let mut mmu: MMU = MMU::new();
+        mmu.load_cartridge(Cartridge::new(rom.into_boxed_slice()).unwrap());
+        CPU::new(mmu)
+    }
+
+    #[test]
+    fn skip_boot_sets_post_boot_state() {
+        let mut cpu = booting_cpu();
+        cpu.skip_boot(Model::Dmg);
+        assert_eq!(cpu.registers.pc, 0x0100);
+        assert_eq!(cpu.registers.sp, 0xfffe);
+        assert_eq!((cpu.registers.a, cpu.registers.b, cpu.registers.c), (0x01, 0x00, 0x13));
+        assert_eq!((cpu.registers.d, cpu.registers.e, cpu.registers.h, cpu.registers.l), (0x00, 0xd8, 0x01, 0x4d));
+        assert!(cpu.registers.flag(Flag::Z) && !cpu.registers.flag(Flag::N) && cpu.registers.flag(Flag::H) && cpu.registers.flag(Flag::C));
+
+        assert_eq!(cpu.mmu.read_byte(0x0000), 0xaa);
+        assert_eq!(cpu.mmu.read_byte(0xff04), 0xab);
+        assert_eq!(cpu.mmu.read_byte(0xff0f), 0xe1);
+        assert_eq!(cpu.mmu.read_byte(0xff40), 0x91);
+        assert_eq!(cpu.mmu.read_byte(0xff47), 0xfc);
+
+        let mut cpu = booting_cpu();
+        cpu.skip_boot(Model::Mgb);
+        assert_eq!(cpu.registers.a, 0xff);
+        assert_eq!(cpu.mmu.read_byte(0xff04), 0xab);
+
+        let mut cpu = booting_cpu();
+        cpu.skip_boot(Model::Cgb);
+        assert_eq!((cpu.registers.a, cpu.registers.b, cpu.registers.c), (0x11, 0x00, 0x00));
+        assert_eq!((cpu.registers.d, cpu.registers.e, cpu.registers.h, cpu.registers.l), (0xff, 0x56, 0x00, 0x0d));
+        assert!(cpu.registers.flag(Flag::Z) && !cpu.registers.flag(Flag::H) && !cpu.registers.flag(Flag::C));
+        assert_eq!((cpu.registers.pc, cpu.registers.sp), (0x0100, 0xfffe));
+        assert_eq!(cpu.mmu.read_byte(0xff04), 0x26);
+    }
+
+    #[test]
+    fn runs_external_boot_rom() {
+        let mut cpu = booting_cpu();
+        match cpu.mmu.load_boot_rom(&[0; 0x200]) {
+            Err(EmulatorError::BootRomSize(0x200)) => {}
+            result => panic!("Expected a boot ROM size error, got {:?}", result)
+        }
+        assert_eq!(cpu.mmu.read_byte(0x0000), 0x31); // LD SP,d16 from the built in one
+
+        let mut boot_rom = [0; 0x100];
+        boot_rom[0x0000] = 0x18; // JR -2
+        boot_rom[0x0001] = 0xfe;
+        cpu.mmu.load_boot_rom(&boot_rom).unwrap();
+        cpu.step(&mut NoDebugger).unwrap();
+        assert_eq!(cpu.registers.pc, 0x0000);
+        cpu.mmu.write_byte(0xff50, 1);
+        assert_eq!(cpu.mmu.read_byte(0x0000), 0xaa);
     }
 }
@@ -1,3 +1,4 @@
+#[derive(Debug, PartialEq)]
 pub enum Data {
     Byte(u8),
     SignedByte(i8),
@@ -1,10 +1,35 @@
 use memory_map::{ReadByte};
+use mmu::MMU;
+use std::fs;
 use std::io::{stdin, stdout, Write};
-use std::process::exit;
-use cpu::CPU;
+use cpu::{CPU, Flag};
+use error::EmulatorError;
+use rewind::{self, Rewind};
 use std::u16;
 use std::str::SplitWhitespace;
 
+/// Lets a debugger watch over the CPU as it runs
+pub trait Hook<B> {
+    /// Called between instructions, before interrupts are serviced
+    fn between_instructions(&mut self, cpu: &CPU<B>);
+
+    /// Called once the opcode at `pc` has been fetched, before it executes.
+    /// Returning true abandons the instruction because the machine state was
+    /// replaced.
+    fn before_instruction(&mut self, cpu: &mut CPU<B>, pc: u16, instruction: u8) -> bool;
+}
+
+/// Runs the CPU without any debugging, against any bus
+pub struct NoDebugger;
+
+impl<B> Hook<B> for NoDebugger {
+    fn between_instructions(&mut self, _cpu: &CPU<B>) {}
+
+    fn before_instruction(&mut self, _cpu: &mut CPU<B>, _pc: u16, _instruction: u8) -> bool {
+        false
+    }
+}
+
 #[derive(Clone)]
 enum Command {
     AddInstrBreak(u8),
@@ -13,8 +38,11 @@ enum Command {
     DeleteBreak(usize),
     Exit,
     ListBreakpoints,
+    LoadState(String),
     Memory(u16),
     Registers,
+    Rewind(usize),
+    SaveState(String),
     Step,
     Invalid(String),
     Watch(Box<Command>)
@@ -31,7 +59,12 @@ pub struct Debugger {
     instruction: u8,
     pc: u16,
     step: bool,
-    watches: Vec<Command>
+    watches: Vec<Command>,
+    exit: bool,
+    rewind: Rewind,
+    // Set when a command replaced the machine state mid-instruction
+    restored: bool,
+    enabled: bool
 }
 
 impl Debugger {
@@ -41,27 +74,45 @@ impl Debugger {
             instruction: 0,
             pc: 0,
             step: false,
-            watches: vec![]
+            watches: vec![],
+            exit: false,
+            rewind: Rewind::new(rewind::DEFAULT_CAPACITY),
+            restored: false,
+            enabled: true
         }
     }
 
+    /// A debugger that never stops the CPU or records frames, for running
+    /// without a terminal
+    pub fn disabled() -> Debugger {
+        Self { enabled: false, ..Debugger::new() }
+    }
+
     pub fn set_pc(&mut self, pc: u16) {
         self.pc = pc;
     }
 
     pub fn add_pc_break(&mut self, pc: u16) {
+        if !self.enabled { return; }
         self.breakpoints.push(Breakpoint::Pc(pc));
     }
 
     pub fn add_instr_break(&mut self, instruction: u8) {
+        if !self.enabled { return; }
         self.breakpoints.push(Breakpoint::Instruction(instruction));
     }
 
+    /// Whether the user asked to quit, so the run loop can stop and clean up
+    pub fn exit_requested(&self) -> bool {
+        self.exit
+    }
+
     pub fn set_instruction(&mut self, instruction: u8) {
         self.instruction = instruction;
     }
 
     pub fn debug(&mut self, cpu: &mut CPU) {
+        if !self.enabled { return; }
         self.run_watches(cpu);
         if self.breakpoints.contains(&Breakpoint::Pc(self.pc))  ||
                 self.breakpoints.contains(&Breakpoint::Instruction(self.instruction)) ||
@@ -71,6 +122,14 @@ impl Debugger {
         }
     }
 
+    /// Whether a command loaded another state while the CPU was stopped at an
+    /// instruction, which must then be abandoned. Clears the flag.
+    fn take_restored(&mut self) -> bool {
+        let restored = self.restored;
+        self.restored = false;
+        restored
+    }
+
     fn list_breakpoints(&self) {
         for (i, bp) in self.breakpoints.iter().enumerate() {
             match bp {
@@ -86,10 +145,16 @@ impl Debugger {
             Command::AddPcBreak(pc) => self.add_pc_break(pc),
             Command::AddInstrBreak(instr) => self.add_instr_break(instr),
             Command::DeleteBreak(i) => { self.breakpoints.remove(i); },
-            Command::Exit => exit(0),
+            Command::Exit => {
+                self.exit = true;
+                return false;
+            },
             Command::ListBreakpoints => self.list_breakpoints(),
+            Command::LoadState(path) => self.load_state(cpu, &path),
+            Command::SaveState(path) => self.save_state(cpu, &path),
             Command::Memory(address) => self.show_memory(cpu, address),
             Command::Registers => self.show_state(cpu),
+            Command::Rewind(frames) => self.rewind(cpu, frames),
             Command::Step => {
                 self.step = true;
                 return false;
@@ -134,12 +199,63 @@ op = {:02x}",
                 cpu.registers.d, cpu.registers.e,
                 cpu.registers.h, cpu.registers.l,
                 cpu.registers.sp, self.pc,
-                cpu.flags.z, cpu.flags.n, cpu.flags.h, cpu.flags.c,
+                cpu.registers.flag(Flag::Z), cpu.registers.flag(Flag::N),
+                cpu.registers.flag(Flag::H), cpu.registers.flag(Flag::C),
                 cpu.interrupts,
                 self.instruction
                 );
     }
 
+    fn save_state(&self, cpu: &mut CPU, path: &str) {
+        // The current instruction has already been fetched, save the state
+        // from before it so loading runs it again
+        let pc = cpu.registers.pc;
+        cpu.registers.pc = self.pc;
+        let state = cpu.save_state();
+        cpu.registers.pc = pc;
+
+        match fs::write(path, state) {
+            Ok(()) => println!("Saved state to {}", path),
+            Err(error) => println!("Couldn't save state: {}", error)
+        }
+    }
+
+    fn load_state(&mut self, cpu: &mut CPU, path: &str) {
+        let result = fs::read(path).map_err(EmulatorError::from)
+            .and_then(|state| cpu.load_state(&state));
+        match result {
+            Ok(()) => {
+                self.restored(cpu);
+                println!("Loaded state from {}", path);
+            }
+            Err(error) => println!("Couldn't load state: {}", error)
+        }
+    }
+
+    fn rewind(&mut self, cpu: &mut CPU, frames: usize) {
+        let result = match self.rewind.rewind(frames) {
+            Some(state) => cpu.load_state(&state),
+            None => {
+                println!("Can only rewind {} frames", self.rewind.len().saturating_sub(1));
+                return;
+            }
+        };
+        match result {
+            Ok(()) => {
+                self.restored(cpu);
+                println!("Rewound {} frames to pc = {:04x}", frames, self.pc);
+            }
+            Err(error) => println!("Couldn't rewind: {}", error)
+        }
+    }
+
+    /// Points the debugger at the instruction the restored state resumes from
+    fn restored(&mut self, cpu: &CPU) {
+        self.restored = true;
+        self.pc = cpu.registers.pc;
+        self.instruction = cpu.mmu.read_byte(self.pc);
+    }
+
     fn show_memory(&self, cpu: &CPU, address: u16) {
         let mem_value = cpu.mmu.read_byte(address);
         println!("Memory [{:04x}] = {:02x}", address, mem_value);
@@ -149,7 +265,10 @@ op = {:02x}",
         print!("gbrs> ");
         stdout().flush().ok().expect("Couldn't flush stdout");
         let mut input = String::new();
-        stdin().read_line(&mut input).expect("Couldn't read stdin");
+        // End of input quits, rather than prompting forever
+        if stdin().read_line(&mut input).expect("Couldn't read stdin") == 0 {
+            return Command::Exit;
+        }
         let mut cmd = input.split_whitespace();
         self.parse_command(&mut cmd)
     }
@@ -174,6 +293,25 @@ op = {:02x}",
                 }
             },
             Some("r") => Command::Registers,
+            Some("save") => {
+                match cmd.next() {
+                    Some(path) => Command::SaveState(path.to_string()),
+                    None       => Command::Invalid("Expected file name".to_string())
+                }
+            }
+            Some("load") => {
+                match cmd.next() {
+                    Some(path) => Command::LoadState(path.to_string()),
+                    None       => Command::Invalid("Expected file name".to_string())
+                }
+            }
+            Some("rewind") => {
+                match cmd.next().map(|n| n.parse()) {
+                    Some(Ok(frames)) => Command::Rewind(frames),
+                    Some(Err(_)) => Command::Invalid("Couldn't parse frame count".to_string()),
+                    _            => Command::Invalid("Expected frame count".to_string())
+                }
+            }
             Some("s") => Command::Step,
             Some("bp") => {
                 match cmd.next().map(|n| u16::from_str_radix(n, 16)) {
@@ -188,3 +326,24 @@ op = {:02x}",
         }
     }
 }
+
+impl Hook<MMU> for Debugger {
+    /// Snapshots the machine for `rewind` at the start of each frame
+    fn between_instructions(&mut self, cpu: &CPU) {
+        if !self.enabled { return; }
+        self.rewind.record(cpu);
+    }
+
+    fn before_instruction(&mut self, cpu: &mut CPU, pc: u16, instruction: u8) -> bool {
+        self.set_pc(pc);
+
+        // Automatically break if we enter invalid program address space
+        if pc >= 0x7fff {
+            self.add_pc_break(pc);
+        }
+
+        self.set_instruction(instruction);
+        self.debug(cpu);
+        self.take_restored()
+    }
+}
@@ -33,9 +33,9 @@ impl Disassembler {
     }
 
     fn take_word(&mut self) -> u16 {
-        let immediate = self.mmu.read_word(self.pc);
-        self.pc += 2;
-        immediate
+        let lower = self.take_byte() as u16;
+        let upper = self.take_byte() as u16;
+        (upper << 8) | lower
     }
 
     // Addressing modes
@@ -108,6 +108,18 @@ impl Disassembler {
         "A".to_string()
     }
 
+    fn register_af(&self) -> String {
+        "AF".to_string()
+    }
+
+    fn register_bc(&self) -> String {
+        "BC".to_string()
+    }
+
+    fn register_de(&self) -> String {
+        "DE".to_string()
+    }
+
     fn register_hl(&self) -> String {
         "HL".to_string()
     }
@@ -126,6 +138,10 @@ impl Disassembler {
         println!("STOP");
     }
 
+    fn illegal(&self, op: u8) {
+        println!("DB ${:02x} ; illegal", op);
+    }
+
     fn disable_interrupts(&self) {
         println!("DI");
     }
@@ -224,36 +240,12 @@ impl Disassembler {
         println!("LDH {}, {}", loc, val);
     }
 
-    fn pop_bc(&self) {
-        println!("POP BC");
-    }
-
-    fn pop_de(&self) {
-        println!("POP DE");
-    }
-
-    fn pop_hl(&self) {
-        println!("POP HL");
-    }
-
-    fn pop_af(&self) {
-        println!("POP AF");
+    fn pop(&self, register: String) {
+        println!("POP {}", register);
     }
 
-    fn push_bc(&self) {
-        println!("PUSH BC");
-    }
-
-    fn push_de(&self) {
-        println!("PUSH DE");
-    }
-
-    fn push_hl(&self) {
-        println!("PUSH HL");
-    }
-
-    fn push_af(&self) {
-        println!("PUSH AF");
+    fn push(&self, register: String) {
+        println!("PUSH {}", register);
     }
 
     // Increments & Decrements
@@ -390,10 +382,38 @@ impl Disassembler {
         println!("RRCA");
     }
 
+    fn rlc(&self, loc: String) {
+        println!("RLC {}", loc);
+    }
+
+    fn rrc(&self, loc: String) {
+        println!("RRC {}", loc);
+    }
+
     fn rl(&self, loc: String) {
         println!("RL {}", loc);
     }
 
+    fn rr(&self, loc: String) {
+        println!("RR {}", loc);
+    }
+
+    fn sla(&self, loc: String) {
+        println!("SLA {}", loc);
+    }
+
+    fn sra(&self, loc: String) {
+        println!("SRA {}", loc);
+    }
+
+    fn swap(&self, loc: String) {
+        println!("SWAP {}", loc);
+    }
+
+    fn srl(&self, loc: String) {
+        println!("SRL {}", loc);
+    }
+
     fn rla(&self) {
         println!("RLA");
     }
@@ -495,4 +515,12 @@ impl Disassembler {
     fn bit(&self, bit: u8, am: String) {
         println!("BIT {},{}", bit, am);
     }
+
+    fn res(&self, bit: u8, am: String) {
+        println!("RES {},{}", bit, am);
+    }
+
+    fn set(&self, bit: u8, am: String) {
+        println!("SET {},{}", bit, am);
+    }
 }
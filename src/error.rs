@@ -0,0 +1,65 @@
+use std::error;
+use std::fmt;
+use std::io;
+use boot;
+use cartridge::CartridgeType;
+
+/// Everything that can stop emulation. Callers running many ROMs can report
+/// these and move on to the next one.
+#[derive(Debug)]
+pub enum EmulatorError {
+    /// Reading a ROM or other file failed
+    Io(io::Error),
+    /// The header names a cartridge type without an emulated memory bank controller
+    UnsupportedCartridge(CartridgeType),
+    /// The CPU executed one of the unused opcodes, which hang it until reset
+    IllegalOpcode { opcode: u8, address: u16 },
+    /// A save state is truncated or isn't a save state at all
+    CorruptState,
+    /// A save state was written by a build with a different state layout
+    StateVersion { found: u32, expected: u32 },
+    /// A save state was made while running a different ROM
+    StateRomMismatch,
+    /// A boot ROM file isn't the size of the boot ROM area
+    BootRomSize(usize)
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulatorError::Io(ref error) => write!(f, "{}", error),
+            EmulatorError::UnsupportedCartridge(CartridgeType::Unknown(code)) => {
+                write!(f, "Unknown cartridge type {:02x}", code)
+            }
+            EmulatorError::UnsupportedCartridge(ref cartridge_type) => {
+                write!(f, "Unsupported cartridge type {:?}", cartridge_type)
+            }
+            EmulatorError::IllegalOpcode { opcode, address } => {
+                write!(f, "Illegal opcode {:02x} at {:04x}", opcode, address)
+            }
+            EmulatorError::CorruptState => write!(f, "Save state is corrupt"),
+            EmulatorError::StateVersion { found, expected } => {
+                write!(f, "Save state version {} is not supported, expected {}", found, expected)
+            }
+            EmulatorError::StateRomMismatch => write!(f, "Save state belongs to a different ROM"),
+            EmulatorError::BootRomSize(size) => {
+                write!(f, "Boot ROM is {} bytes, expected {}", size, boot::BOOT_ROM_SIZE)
+            }
+        }
+    }
+}
+
+impl error::Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            EmulatorError::Io(ref error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for EmulatorError {
+    fn from(error: io::Error) -> EmulatorError {
+        EmulatorError::Io(error)
+    }
+}
@@ -1,11 +1,25 @@
+use error::EmulatorError;
+use interrupt::Interrupt;
 use memory_map::{ReadByte, WriteByte};
+use state::{Snapshot, StateReader, StateWriter};
 
 const BASE: u16 = 0xff40;
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
 pub const OAM_SIZE: usize = SCREEN_WIDTH;
+const MAX_SPRITES_PER_LINE: usize = 10;
 
-#[derive(Debug)]
+// Cycles spent in each mode of a visible line, and on each line of VBlank
+const OAM_READ_CYCLES: u16 = 80;
+const VRAM_READ_CYCLES: u16 = 172;
+const H_BLANK_CYCLES: u16 = 204;
+const LINE_CYCLES: u16 = 456;
+
+/// Registers saved in snapshots by value, rather than field by field. LY is
+/// left out as it's read only.
+const STATE_REGISTERS: [u16; 10] = [0xff40, 0xff41, 0xff42, 0xff43, 0xff45, 0xff47, 0xff48, 0xff49, 0xff4a, 0xff4b];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum LineMode {
     HBlank = 0,
     VBlank = 1,
@@ -13,22 +27,38 @@ enum LineMode {
     VRAMRead = 3
 }
 
-#[derive(Debug, Copy, Clone)]
-enum Shade {
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Shade {
     White,
     LightGray,
     DarkGray,
     Black
 }
 
+/// An entry in OAM
+#[derive(Debug, Copy, Clone)]
+struct Sprite {
+    y: u8,
+    x: u8,
+    tile: u8,
+    attributes: u8
+}
+
+impl Sprite {
+    fn behind_background(&self) -> bool { self.attributes & 0b1000_0000 != 0 }
+    fn y_flip(&self) -> bool { self.attributes & 0b0100_0000 != 0 }
+    fn x_flip(&self) -> bool { self.attributes & 0b0010_0000 != 0 }
+    fn palette(&self) -> u8 { (self.attributes & 0b0001_0000) >> 4 }
+}
+
 impl Shade {
+    /// Decodes a 2-bit palette entry, ignoring the higher bits
     fn from_u8(value: u8) -> Shade {
-        match value {
+        match value & 0b11 {
             0 => Shade::White,
             1 => Shade::LightGray,
             2 => Shade::DarkGray,
-            3 => Shade::Black,
-            _ => { panic!("Invalid shade: {}", value); }
+            _ => Shade::Black
         }
     }
 
@@ -42,6 +72,36 @@ impl Shade {
     }
 }
 
+/// RGB colors for each shade, for turning frames into images
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+impl Palette {
+    pub const GRAYSCALE: Palette = Palette([[0xff, 0xff, 0xff], [0xaa, 0xaa, 0xaa], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]]);
+    /// The green tint of the original Game Boy screen
+    pub const DMG: Palette = Palette([[0x9b, 0xbc, 0x0f], [0x8b, 0xac, 0x0f], [0x30, 0x62, 0x30], [0x0f, 0x38, 0x0f]]);
+    /// The Game Boy Pocket's olive grays
+    pub const POCKET: Palette = Palette([[0xc4, 0xcf, 0xa1], [0x8b, 0x95, 0x6d], [0x4d, 0x53, 0x3c], [0x1f, 0x1f, 0x1f]]);
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        match name {
+            "gray" | "grayscale" => Some(Palette::GRAYSCALE),
+            "dmg" => Some(Palette::DMG),
+            "pocket" => Some(Palette::POCKET),
+            _ => None
+        }
+    }
+
+    pub fn rgb(&self, shade: Shade) -> [u8; 3] {
+        self.0[shade.to_u8() as usize]
+    }
+
+    /// Converts a frame to three bytes per pixel
+    pub fn apply(&self, frame: &[Shade]) -> Vec<u8> {
+        frame.iter().flat_map(|&shade| self.rgb(shade).to_vec()).collect()
+    }
+}
+
 pub struct GPU {
     scroll_y: u8,
     scroll_x: u8,
@@ -52,6 +112,8 @@ pub struct GPU {
     pub oam: [u8; OAM_SIZE],
 
     lcd_on: bool,               // LCDC
+    window_map_select: u8,
+    window_display_enable: bool,
     bg_tile_select: u8,
     bg_map_select: u8,
     obj_size: u8,
@@ -64,13 +126,30 @@ pub struct GPU {
     h_blank_interrupt: u8,
     lyc: u8,
     line_mode: LineMode,
+    stat_line: bool,
 
     window_position_y: u8,
     window_position_x: u8,
+    window_line: u8,
 
     bg_palette: (Shade, Shade, Shade, Shade),
     obj_0_palette: (Shade, Shade, Shade, Shade),
-    obj_1_palette: (Shade, Shade, Shade, Shade)
+    obj_1_palette: (Shade, Shade, Shade, Shade),
+
+    // Background/window color numbers of the line being drawn, which sprites
+    // need for priority
+    line_colors: [u8; SCREEN_WIDTH],
+    buffer: Box<[Shade]>,
+    framebuffer: Box<[Shade]>,
+    // Frames completed since power on. Bookkeeping for the host rather than
+    // hardware state, so it isn't part of a snapshot
+    frames: u64
+}
+
+impl Default for GPU {
+    fn default() -> GPU {
+        GPU::new()
+    }
 }
 
 impl GPU {
@@ -84,6 +163,8 @@ impl GPU {
             vram: [0; 8192],
             oam: [0; 160],
             lcd_on: true,
+            window_map_select: 0,
+            window_display_enable: false,
             bg_tile_select: 1,
             bg_map_select: 0,
             obj_size: 0,
@@ -95,70 +176,310 @@ impl GPU {
             h_blank_interrupt: 0,
             lyc: 0,
             line_mode: LineMode::OAMRead,
+            stat_line: false,
             window_position_y: 0,
             window_position_x: 0,
+            window_line: 0,
             bg_palette: (Shade::White, Shade::White, Shade::White, Shade::White),
             obj_0_palette: (Shade::White, Shade::White, Shade::White, Shade::White),
-            obj_1_palette: (Shade::White, Shade::White, Shade::White, Shade::White)
+            obj_1_palette: (Shade::White, Shade::White, Shade::White, Shade::White),
+            line_colors: [0; SCREEN_WIDTH],
+            buffer: vec![Shade::White; SCREEN_WIDTH * SCREEN_HEIGHT].into_boxed_slice(),
+            framebuffer: vec![Shade::White; SCREEN_WIDTH * SCREEN_HEIGHT].into_boxed_slice(),
+            frames: 0
         }
     }
 
-    pub fn step(&mut self, cycles: u8) {
-        self.clock = self.clock + (cycles as u16);
+    /// The last complete frame, row by row from the top left
+    pub fn framebuffer(&self) -> &[Shade] {
+        &self.framebuffer
+    }
+
+    /// How many frames have been completed, counted on entering VBlank.
+    /// Doesn't advance while the LCD is off.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Advances the GPU by `cycles` cycles, returning the interrupts it
+    /// requested as IF bits
+    pub fn step(&mut self, cycles: u8) -> u8 {
+        if !self.lcd_on { return 0; }
+
+        let mut interrupts = 0;
+        self.clock += cycles as u16;
 
         match self.line_mode {
             LineMode::HBlank => {
-                if self.clock >= 51 {
-                    if self.current_line == 143 {
+                if self.clock >= H_BLANK_CYCLES {
+                    self.clock -= H_BLANK_CYCLES;
+                    self.current_line += 1;
+                    if self.current_line == SCREEN_HEIGHT as u8 {
                         self.line_mode = LineMode::VBlank;
                         self.render_screen();
+                        interrupts |= Interrupt::VBlank.mask();
                     } else {
                         self.line_mode = LineMode::OAMRead;
                     }
-                    self.current_line = self.current_line + 1;
-                    //self.current_scan += 640;
-                    self.clock = 0;
                 }
             }
             LineMode::VBlank => {
-                if self.clock >= 114 {
-                    self.clock = 0;
-                    self.current_line = self.current_line + 1;
+                if self.clock >= LINE_CYCLES {
+                    self.clock -= LINE_CYCLES;
+                    self.current_line += 1;
                     if self.current_line > 153 {
                         self.current_line = 0;
+                        self.window_line = 0;
                         self.line_mode = LineMode::OAMRead
                     }
                 }
             }
             LineMode::OAMRead => {
-                if self.clock >= 20 {
-                    self.clock = 0;
+                if self.clock >= OAM_READ_CYCLES {
+                    self.clock -= OAM_READ_CYCLES;
                     self.line_mode = LineMode::VRAMRead;
                 }
             }
             LineMode::VRAMRead => {
-                if self.clock >= 43 {
-                    self.clock = 0;
+                if self.clock >= VRAM_READ_CYCLES {
+                    self.clock -= VRAM_READ_CYCLES;
                     self.line_mode = LineMode::HBlank;
                     self.render_scanline();
                 }
             }
         }
+
+        interrupts | self.update_stat_line()
+    }
+
+    /// The STAT interrupt is requested on the rising edge of a single line
+    /// ORed from every enabled source. While any source holds it high, other
+    /// sources becoming active don't request another interrupt.
+    fn update_stat_line(&mut self) -> u8 {
+        let line = (self.h_blank_interrupt == 1 && self.line_mode == LineMode::HBlank) ||
+            (self.v_blank_interrupt == 1 && self.line_mode == LineMode::VBlank) ||
+            (self.oam_interrupt == 1 && self.line_mode == LineMode::OAMRead) ||
+            (self.coincidence_interrupt == 1 && self.current_line == self.lyc);
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        if rising { Interrupt::LcdStat.mask() } else { 0 }
     }
 
     fn render_scanline(&mut self) {
         if !self.lcd_on { return; }
 
-        if self.bg_display_enable {
-            println!("Render scanline background");
-        }
+        self.render_background();
+        self.render_window();
 
         if self.obj_display_enable {
-            println!("Render scanline object");
+            self.render_sprites();
+        }
+    }
+
+    fn render_background(&mut self) {
+        let line = self.current_line as usize * SCREEN_WIDTH;
+
+        if !self.bg_display_enable {
+            for x in 0..SCREEN_WIDTH {
+                self.buffer[line + x] = Shade::White;
+                self.line_colors[x] = 0;
+            }
+            return;
+        }
+
+        let map_base = if self.bg_map_select == 1 { 0x1c00 } else { 0x1800 };
+        let y = self.current_line.wrapping_add(self.scroll_y);
+
+        for x in 0..SCREEN_WIDTH {
+            let x_offset = (x as u8).wrapping_add(self.scroll_x);
+            let map_address = map_base + (y as usize / 8) * 32 + x_offset as usize / 8;
+            let tile = self.vram[map_address];
+            let color = self.tile_color(tile, x_offset % 8, y % 8);
+            self.buffer[line + x] = palette_shade(&self.bg_palette, color);
+            self.line_colors[x] = color;
         }
     }
 
+    /// Draws the window over the background. The window keeps its own line
+    /// counter, which only advances on lines where the window was drawn, so
+    /// hiding it part way down the screen resumes from the same row.
+    fn render_window(&mut self) {
+        if !self.window_display_enable || !self.bg_display_enable ||
+                self.current_line < self.window_position_y || self.window_position_x > 166 {
+            return;
+        }
+
+        let line = self.current_line as usize * SCREEN_WIDTH;
+        let map_base = if self.window_map_select == 1 { 0x1c00 } else { 0x1800 };
+        let y = self.window_line;
+        let start = (self.window_position_x as usize).saturating_sub(7);
+
+        for x in start..SCREEN_WIDTH {
+            let x_offset = (x + 7 - self.window_position_x as usize) as u8;
+            let map_address = map_base + (y as usize / 8) * 32 + x_offset as usize / 8;
+            let tile = self.vram[map_address];
+            let color = self.tile_color(tile, x_offset % 8, y % 8);
+            self.buffer[line + x] = palette_shade(&self.bg_palette, color);
+            self.line_colors[x] = color;
+        }
+
+        self.window_line += 1;
+    }
+
+    /// Draws the sprites on the current line. Only the first ten sprites in OAM
+    /// that overlap the line are drawn; where they overlap each other, the one
+    /// with the lower X coordinate wins, then the one earlier in OAM.
+    fn render_sprites(&mut self) {
+        let height = if self.obj_size == 1 { 16 } else { 8 };
+        let line = self.current_line as i16;
+
+        let mut sprites: Vec<Sprite> = self.oam.chunks(4)
+            .map(|entry| Sprite { y: entry[0], x: entry[1], tile: entry[2], attributes: entry[3] })
+            .filter(|sprite| {
+                let top = sprite.y as i16 - 16;
+                line >= top && line < top + height
+            })
+            .take(MAX_SPRITES_PER_LINE)
+            .collect();
+        // Stable, so sprites at the same X stay in OAM order
+        sprites.sort_by_key(|sprite| sprite.x);
+
+        let offset = self.current_line as usize * SCREEN_WIDTH;
+        let mut drawn = [false; SCREEN_WIDTH];
+
+        for sprite in sprites {
+            let mut row = (line - (sprite.y as i16 - 16)) as u8;
+            if sprite.y_flip() {
+                row = height as u8 - 1 - row;
+            }
+            let tile = if height == 16 { sprite.tile & 0xfe } else { sprite.tile };
+            let palette = if sprite.palette() == 1 { self.obj_1_palette } else { self.obj_0_palette };
+
+            for column in 0..8u8 {
+                let x = sprite.x as i16 - 8 + column as i16;
+                if x < 0 || x >= SCREEN_WIDTH as i16 || drawn[x as usize] {
+                    continue;
+                }
+                let x = x as usize;
+
+                let tile_x = if sprite.x_flip() { 7 - column } else { column };
+                let color = self.sprite_color(tile, tile_x, row);
+                if color == 0 {
+                    continue;
+                }
+
+                // A sprite hidden behind the background still covers the
+                // sprites beneath it
+                drawn[x] = true;
+                if sprite.behind_background() && self.line_colors[x] != 0 {
+                    continue;
+                }
+                self.buffer[offset + x] = palette_shade(&palette, color);
+            }
+        }
+    }
+
+    /// Color number (0-3) of a pixel in a sprite tile, which always use the
+    /// unsigned tile numbering at 0x8000. 8x16 sprites continue into the next
+    /// tile for rows 8-15.
+    fn sprite_color(&self, tile: u8, x: u8, y: u8) -> u8 {
+        let row = tile as usize * 16 + y as usize * 2;
+        let bit = 7 - x;
+        let low = (self.vram[row] >> bit) & 1;
+        let high = (self.vram[row + 1] >> bit) & 1;
+        (high << 1) | low
+    }
+
+    /// Color number (0-3) of a pixel in a background or window tile, honoring
+    /// the signed tile numbering at 0x8800 when LCDC bit 4 is clear
+    fn tile_color(&self, tile: u8, x: u8, y: u8) -> u8 {
+        let tile_address = if self.bg_tile_select == 1 {
+            tile as usize * 16
+        } else {
+            (0x1000 + (tile as i8 as isize) * 16) as usize
+        };
+        let row = tile_address + y as usize * 2;
+        let bit = 7 - x;
+        let low = (self.vram[row] >> bit) & 1;
+        let high = (self.vram[row + 1] >> bit) & 1;
+        (high << 1) | low
+    }
+
     fn render_screen(&mut self) {
+        self.framebuffer.copy_from_slice(&self.buffer);
+        self.frames += 1;
+    }
+}
+
+fn palette_shade(palette: &(Shade, Shade, Shade, Shade), color: u8) -> Shade {
+    match color {
+        0 => palette.0,
+        1 => palette.1,
+        2 => palette.2,
+        _ => palette.3
+    }
+}
+
+impl GPU {
+    fn set_lcdc(&mut self, value: u8) {
+        self.lcd_on = (value & 0b10000000) == 0b10000000;
+        self.window_map_select = (value & 0b01000000) >> 6;
+        self.window_display_enable = (value & 0b00100000) == 0b00100000;
+        self.bg_tile_select = (value & 0b00010000) >> 4;
+        self.bg_map_select = (value & 0b00001000) >> 3;
+        self.obj_size = (value & 0b00000100) >> 2;
+        self.obj_display_enable = (value & 0b00000010) == 0b000000010;
+        self.bg_display_enable = (value & 0b00000001) == 0b00000001;
+    }
+}
+
+impl Snapshot for GPU {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.vram);
+        writer.bytes(&self.oam);
+        writer.bytes(&self.memory);
+        writer.u16(self.clock);
+        writer.u8(self.current_line);
+        writer.u8(self.line_mode as u8);
+        writer.bool(self.stat_line);
+        writer.u8(self.window_line);
+        // The remaining registers are stored as the CPU reads them
+        for &address in STATE_REGISTERS.iter() {
+            writer.u8(self.read_byte(address));
+        }
+        for &shade in self.buffer.iter().chain(self.framebuffer.iter()) {
+            writer.u8(shade.to_u8());
+        }
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        reader.bytes(&mut self.vram)?;
+        reader.bytes(&mut self.oam)?;
+        reader.bytes(&mut self.memory)?;
+        self.clock = reader.u16()?;
+        self.current_line = reader.u8()?;
+        self.line_mode = match reader.u8()? {
+            0 => LineMode::HBlank,
+            1 => LineMode::VBlank,
+            2 => LineMode::OAMRead,
+            3 => LineMode::VRAMRead,
+            _ => return Err(EmulatorError::CorruptState)
+        };
+        self.stat_line = reader.bool()?;
+        self.window_line = reader.u8()?;
+        for &address in STATE_REGISTERS.iter() {
+            let value = reader.u8()?;
+            if address == 0xff40 {
+                // Set directly, as writing LCDC can reset LY
+                self.set_lcdc(value);
+            } else {
+                self.write_byte(address, value);
+            }
+        }
+        for shade in self.buffer.iter_mut().chain(self.framebuffer.iter_mut()) {
+            *shade = Shade::from_u8(reader.u8()?);
+        }
+        Ok(())
     }
 }
 
@@ -170,7 +491,8 @@ impl ReadByte for GPU {
             0xff40 => {
                 let mut value: u8 = 0;
                 if self.lcd_on { value |= 0b10000000; }
-                //TODO bits 5,6
+                value |= (self.window_map_select & 1) << 6;
+                if self.window_display_enable { value |= 0b00100000; }
                 value |= (self.bg_tile_select & 1) << 4;
                 value |= (self.bg_map_select & 1) << 3;
                 value |= (self.obj_size & 1) << 2;
@@ -211,9 +533,9 @@ impl ReadByte for GPU {
             }
             0xff49 => {
                 self.obj_1_palette.0.to_u8() |
-                    self.obj_0_palette.1.to_u8() << 2 |
-                    self.obj_0_palette.2.to_u8() << 4 |
-                    self.obj_0_palette.3.to_u8() << 6
+                    self.obj_1_palette.1.to_u8() << 2 |
+                    self.obj_1_palette.2.to_u8() << 4 |
+                    self.obj_1_palette.3.to_u8() << 6
             }
             0xff4a => { self.window_position_y }
             0xff4b => { self.window_position_x }
@@ -229,12 +551,17 @@ impl WriteByte for GPU {
             0x9800...0x9fff => { self.vram[(address & 0x1fff) as usize] = value }
             0xfe00...0xfe9f => { self.oam[(address & 0xff) as usize] = value }
             0xff40 => {
-                self.lcd_on = (value & 0b10000000) == 0b10000000;
-                self.bg_tile_select = (value & 0b00010000) >> 4;
-                self.bg_map_select = (value & 0b00001000) >> 3;
-                self.obj_size = (value & 0b00000100) >> 2;
-                self.obj_display_enable = (value & 0b00000010) == 0b000000010;
-                self.bg_display_enable = (value & 0b00000001) == 0b00000001;
+                let lcd_on = (value & 0b10000000) == 0b10000000;
+                if self.lcd_on && !lcd_on {
+                    // LY stays at 0 while the LCD is off
+                    self.current_line = 0;
+                    self.window_line = 0;
+                    self.clock = 0;
+                    self.line_mode = LineMode::HBlank;
+                } else if !self.lcd_on && lcd_on {
+                    self.line_mode = LineMode::OAMRead;
+                }
+                self.set_lcdc(value);
             }
             0xff41 => {
                 self.coincidence_interrupt = (value & 0b0100_0000) >> 6;
@@ -275,3 +602,253 @@ impl WriteByte for GPU {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use memory_map::{ReadByte, WriteByte};
+    use super::{GPU, Palette, Shade, SCREEN_WIDTH};
+
+    fn gpu_with_tile(tile_address: u16) -> GPU {
+        let mut gpu = GPU::new();
+        gpu.write_byte(0xff47, 0b1110_0100);
+        // A tile whose top row is colors 0, 1, 2, 3, 0, 1, 2, 3
+        gpu.write_byte(tile_address, 0b0101_0101);
+        gpu.write_byte(tile_address + 1, 0b0011_0011);
+        gpu
+    }
+
+    #[test]
+    fn renders_background_tiles() {
+        let mut gpu = gpu_with_tile(0x8010);
+        gpu.write_byte(0xff40, 0b1001_0001);
+        gpu.write_byte(0x9800, 1);
+        gpu.render_scanline();
+        gpu.render_screen();
+
+        let row = &gpu.framebuffer()[0..8];
+        assert_eq!(row, &[Shade::White, Shade::LightGray, Shade::DarkGray, Shade::Black,
+                          Shade::White, Shade::LightGray, Shade::DarkGray, Shade::Black]);
+    }
+
+    #[test]
+    fn renders_signed_tile_numbers() {
+        let mut gpu = gpu_with_tile(0x8ff0);
+        gpu.write_byte(0xff40, 0b1000_0001);
+        gpu.write_byte(0x9800, 0xff);
+        gpu.render_scanline();
+        gpu.render_screen();
+
+        assert_eq!(gpu.framebuffer()[3], Shade::Black);
+    }
+
+    #[test]
+    fn lcdc_window_bits_round_trip() {
+        let mut gpu = GPU::new();
+        gpu.write_byte(0xff40, 0b1110_0001);
+        assert_eq!(gpu.read_byte(0xff40), 0b1110_0001);
+    }
+
+    #[test]
+    fn renders_window_over_background() {
+        let mut gpu = gpu_with_tile(0x8010);
+        gpu.write_byte(0xff40, 0b1111_0001);
+        gpu.write_byte(0x9c00, 1);
+        gpu.write_byte(0xff4a, 0);
+        gpu.write_byte(0xff4b, 7 + 4);
+        gpu.render_scanline();
+        gpu.render_screen();
+
+        assert_eq!(gpu.framebuffer()[3], Shade::White);
+        assert_eq!(gpu.framebuffer()[4 + 3], Shade::Black);
+    }
+
+    #[test]
+    fn window_line_counter_only_advances_when_drawn() {
+        let mut gpu = gpu_with_tile(0x8010);
+        gpu.write_byte(0xff40, 0b1111_0001);
+        gpu.write_byte(0xff4b, 7);
+        gpu.render_scanline();
+        assert_eq!(gpu.window_line, 1);
+
+        gpu.write_byte(0xff40, 0b1101_0001);
+        gpu.current_line = 1;
+        gpu.render_scanline();
+        assert_eq!(gpu.window_line, 1);
+
+        gpu.write_byte(0xff40, 0b1111_0001);
+        gpu.current_line = 2;
+        gpu.render_scanline();
+        assert_eq!(gpu.window_line, 2);
+    }
+
+    fn write_sprite(gpu: &mut GPU, index: usize, y: u8, x: u8, tile: u8, attributes: u8) {
+        gpu.oam[index * 4] = y;
+        gpu.oam[index * 4 + 1] = x;
+        gpu.oam[index * 4 + 2] = tile;
+        gpu.oam[index * 4 + 3] = attributes;
+    }
+
+    fn gpu_with_sprite_tiles() -> GPU {
+        let mut gpu = GPU::new();
+        gpu.write_byte(0xff40, 0b1000_0011);
+        gpu.write_byte(0xff47, 0b1110_0100);
+        gpu.write_byte(0xff48, 0b1110_0100);
+        gpu.write_byte(0xff49, 0b0100_0000);
+        // Tile 1: a solid color 3 row followed by a row with only the leftmost pixel set
+        gpu.write_byte(0x8010, 0xff);
+        gpu.write_byte(0x8011, 0xff);
+        gpu.write_byte(0x8012, 0x80);
+        gpu.write_byte(0x8013, 0x80);
+        // Tile 2: solid color 1
+        for i in 0..16 {
+            gpu.write_byte(0x8020 + i, if i % 2 == 0 { 0xff } else { 0x00 });
+        }
+        gpu
+    }
+
+    #[test]
+    fn renders_sprites_with_palettes_and_flips() {
+        let mut gpu = gpu_with_sprite_tiles();
+        write_sprite(&mut gpu, 0, 16, 8, 1, 0);
+        write_sprite(&mut gpu, 1, 16, 16, 1, 0b0001_0000);
+        write_sprite(&mut gpu, 2, 15, 24, 1, 0b0010_0000);
+        gpu.render_scanline();
+        gpu.render_screen();
+
+        let frame = gpu.framebuffer();
+        assert_eq!(frame[0], Shade::Black);
+        assert_eq!(frame[8], Shade::LightGray);
+        // Second row of a flipped sprite has its single pixel on the right
+        assert_eq!(frame[16], Shade::White);
+        assert_eq!(frame[23], Shade::Black);
+    }
+
+    #[test]
+    fn renders_tall_sprites() {
+        let mut gpu = gpu_with_sprite_tiles();
+        gpu.write_byte(0xff40, 0b1000_0111);
+        write_sprite(&mut gpu, 0, 8, 8, 3, 0);
+        gpu.render_scanline();
+        gpu.render_screen();
+
+        // Line 0 is row 8 of the sprite, the top of the blank tile 3 (the low
+        // bit of the tile index is ignored); flipping brings row 7 of tile 2
+        // onto the line instead
+        assert_eq!(gpu.framebuffer()[0], Shade::White);
+        write_sprite(&mut gpu, 0, 8, 8, 3, 0b0100_0000);
+        gpu.render_scanline();
+        gpu.render_screen();
+        assert_eq!(gpu.framebuffer()[0], Shade::LightGray);
+    }
+
+    #[test]
+    fn lower_x_sprite_wins() {
+        let mut gpu = gpu_with_sprite_tiles();
+        write_sprite(&mut gpu, 0, 16, 12, 2, 0);
+        write_sprite(&mut gpu, 1, 16, 8, 1, 0);
+        gpu.render_scanline();
+        gpu.render_screen();
+
+        assert_eq!(gpu.framebuffer()[4], Shade::Black);
+        assert_eq!(gpu.framebuffer()[8], Shade::LightGray);
+    }
+
+    #[test]
+    fn ten_sprites_per_line() {
+        let mut gpu = gpu_with_sprite_tiles();
+        for i in 0..11 {
+            write_sprite(&mut gpu, i, 16, 8 + 8 * i as u8, 1, 0);
+        }
+        gpu.render_scanline();
+        gpu.render_screen();
+
+        assert_eq!(gpu.framebuffer()[9 * 8], Shade::Black);
+        assert_eq!(gpu.framebuffer()[10 * 8], Shade::White);
+    }
+
+    #[test]
+    fn background_over_sprite_priority() {
+        let mut gpu = gpu_with_sprite_tiles();
+        gpu.write_byte(0xff40, 0b1001_0011);
+        gpu.write_byte(0x9800, 2);
+        write_sprite(&mut gpu, 0, 16, 8, 1, 0b1000_0000);
+        write_sprite(&mut gpu, 1, 16, 16, 1, 0b1000_0000);
+        gpu.render_scanline();
+        gpu.render_screen();
+
+        assert_eq!(gpu.framebuffer()[0], Shade::LightGray);
+        assert_eq!(gpu.framebuffer()[8], Shade::Black);
+    }
+
+    fn step_lines(gpu: &mut GPU, lines: usize) -> Vec<u8> {
+        (0..lines * 456 / 4).map(|_| gpu.step(4)).filter(|&i| i != 0).collect()
+    }
+
+    #[test]
+    fn requests_vblank_once_per_frame() {
+        let mut gpu = GPU::new();
+        let interrupts = step_lines(&mut gpu, 154 * 2);
+        assert_eq!(interrupts, vec![0b01, 0b01]);
+        assert_eq!(gpu.frame_count(), 2);
+    }
+
+    #[test]
+    fn palette_colors_frames() {
+        let frame = [Shade::White, Shade::Black, Shade::DarkGray];
+        assert_eq!(Palette::GRAYSCALE.apply(&frame), vec![0xff, 0xff, 0xff, 0, 0, 0, 0x55, 0x55, 0x55]);
+        assert_eq!(Palette::from_name("dmg"), Some(Palette::DMG));
+        assert_eq!(Palette::from_name("sepia"), None);
+    }
+
+    #[test]
+    fn vblank_starts_at_line_144() {
+        let mut gpu = GPU::new();
+        step_lines(&mut gpu, 143);
+        for _ in 0..456 / 4 - 1 { gpu.step(4); }
+        assert_eq!(gpu.read_byte(0xff44), 143);
+        gpu.step(4);
+        assert_eq!(gpu.read_byte(0xff44), 144);
+        assert_eq!(gpu.read_byte(0xff41) & 0b11, 1);
+    }
+
+    #[test]
+    fn requests_stat_on_lyc_match() {
+        let mut gpu = GPU::new();
+        gpu.write_byte(0xff45, 10);
+        gpu.write_byte(0xff41, 0b0100_0000);
+        let interrupts = step_lines(&mut gpu, 154);
+        assert_eq!(interrupts, vec![0b10, 0b01]);
+        assert_eq!(gpu.read_byte(0xff41) & 0b100, 0);
+    }
+
+    #[test]
+    fn requests_stat_on_each_hblank() {
+        let mut gpu = GPU::new();
+        gpu.write_byte(0xff41, 0b0000_1000);
+        let interrupts = step_lines(&mut gpu, 154);
+        assert_eq!(interrupts.iter().filter(|&&i| i & 0b10 != 0).count(), 144);
+    }
+
+    #[test]
+    fn stat_blocking() {
+        let mut gpu = GPU::new();
+        // HBlank of line 143 runs straight into VBlank without the line dropping
+        gpu.write_byte(0xff41, 0b0001_1000);
+        let interrupts = step_lines(&mut gpu, 154);
+        assert_eq!(interrupts.iter().filter(|&&i| i & 0b10 != 0).count(), 144);
+    }
+
+    #[test]
+    fn applies_scroll() {
+        let mut gpu = gpu_with_tile(0x8010);
+        gpu.write_byte(0xff40, 0b1001_1001);
+        gpu.write_byte(0x9c21, 1);
+        gpu.write_byte(0xff42, 8);
+        gpu.write_byte(0xff43, 10);
+        gpu.render_scanline();
+        gpu.render_screen();
+
+        assert_eq!(gpu.framebuffer()[0], Shade::DarkGray);
+        assert_eq!(gpu.framebuffer()[SCREEN_WIDTH - 1], Shade::White);
+    }
+}
@@ -0,0 +1,34 @@
+/// Interrupt sources, in priority order. The discriminant is the bit the
+/// interrupt occupies in both IE (0xffff) and IF (0xff0f).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interrupt {
+    VBlank = 0,
+    LcdStat = 1,
+    Timer = 2,
+    Serial = 3,
+    Joypad = 4
+}
+
+impl Interrupt {
+    /// Returns the highest priority interrupt set in `pending`, if any.
+    pub fn from_pending(pending: u8) -> Option<Interrupt> {
+        match pending & 0x1f {
+            0 => None,
+            p => match p.trailing_zeros() {
+                0 => Some(Interrupt::VBlank),
+                1 => Some(Interrupt::LcdStat),
+                2 => Some(Interrupt::Timer),
+                3 => Some(Interrupt::Serial),
+                _ => Some(Interrupt::Joypad)
+            }
+        }
+    }
+
+    pub fn mask(self) -> u8 {
+        1 << (self as u8)
+    }
+
+    pub fn vector(self) -> u16 {
+        0x40 + (self as u16) * 8
+    }
+}
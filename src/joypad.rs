@@ -1,4 +1,19 @@
+use error::EmulatorError;
+use interrupt::Interrupt;
 use memory_map::{ReadByte, WriteByte};
+use state::{Snapshot, StateReader, StateWriter};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start
+}
 
 pub struct Joypad {
     select_button_keys: bool,
@@ -10,7 +25,15 @@ pub struct Joypad {
     start: bool,
     select: bool,
     b: bool,
-    a: bool
+    a: bool,
+    lines: u8,
+    interrupt: bool
+}
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad::new()
+    }
 }
 
 impl Joypad {
@@ -25,21 +48,95 @@ impl Joypad {
             start: false,
             select: false,
             b: false,
-            a: false
+            a: false,
+            lines: 0b0000_1111,
+            interrupt: false
         }
     }
+
+    pub fn press(&mut self, button: Button) {
+        self.set(button, true);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.set(button, false);
+    }
+
+    /// Returns and clears the interrupts requested since the last call, as IF bits
+    pub fn take_interrupts(&mut self) -> u8 {
+        let requested = if self.interrupt { Interrupt::Joypad.mask() } else { 0 };
+        self.interrupt = false;
+        requested
+    }
+
+    fn set(&mut self, button: Button, pressed: bool) {
+        match button {
+            Button::Right => self.right = pressed,
+            Button::Left => self.left = pressed,
+            Button::Up => self.up = pressed,
+            Button::Down => self.down = pressed,
+            Button::A => self.a = pressed,
+            Button::B => self.b = pressed,
+            Button::Select => self.select = pressed,
+            Button::Start => self.start = pressed
+        }
+        self.update_lines();
+    }
+
+    /// The input lines P10-P13 are pulled low by a pressed key in a selected
+    /// group. Any line going from high to low requests the joypad interrupt.
+    fn update_lines(&mut self) {
+        let mut pressed = 0;
+        if self.select_directional_keys {
+            if self.down { pressed |= 0b0000_1000; }
+            if self.up { pressed |= 0b0000_0100; }
+            if self.left { pressed |= 0b0000_0010; }
+            if self.right { pressed |= 0b0000_0001; }
+        }
+        if self.select_button_keys {
+            if self.start { pressed |= 0b0000_1000; }
+            if self.select { pressed |= 0b0000_0100; }
+            if self.b { pressed |= 0b0000_0010; }
+            if self.a { pressed |= 0b0000_0001; }
+        }
+
+        let lines = !pressed & 0b0000_1111;
+        if self.lines & !lines != 0 {
+            self.interrupt = true;
+        }
+        self.lines = lines;
+    }
+}
+
+impl Snapshot for Joypad {
+    fn write_state(&self, writer: &mut StateWriter) {
+        for &flag in &[self.select_button_keys, self.select_directional_keys,
+                       self.down, self.up, self.left, self.right,
+                       self.start, self.select, self.b, self.a, self.interrupt] {
+            writer.bool(flag);
+        }
+        writer.u8(self.lines);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        for flag in &mut [&mut self.select_button_keys, &mut self.select_directional_keys,
+                          &mut self.down, &mut self.up, &mut self.left, &mut self.right,
+                          &mut self.start, &mut self.select, &mut self.b, &mut self.a,
+                          &mut self.interrupt] {
+            **flag = reader.bool()?;
+        }
+        self.lines = reader.u8()?;
+        Ok(())
+    }
 }
 
 impl ReadByte for Joypad {
     fn read_byte(&self, _address: u16) -> u8 {
-        let mut value = 0;
-        if self.select_button_keys { value |= 0b0010_0000; }
-        if self.select_directional_keys { value |= 0b0001_000; }
-        if self.down || self.start { value |= 0b0000_1000; }
-        if self.up || self.select { value |= 0b0000_0100; }
-        if self.left || self.b { value |= 0b0000_0010; }
-        if self.right || self.b { value |= 0b0000_0001; }
-        !value
+        // Selection bits read back as written (0 = selected), unused bits read 1
+        let mut value = 0b1111_0000 | self.lines;
+        if self.select_button_keys { value &= !0b0010_0000; }
+        if self.select_directional_keys { value &= !0b0001_0000; }
+        value
     }
 }
 
@@ -48,5 +145,55 @@ impl WriteByte for Joypad {
         let value = !value;
         self.select_button_keys = value & 0b0010_0000 == 0b0010_0000;
         self.select_directional_keys = value & 0b0001_0000 == 0b0001_0000;
+        self.update_lines();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory_map::{ReadByte, WriteByte};
+    use super::{Button, Joypad};
+
+    #[test]
+    fn reads_selected_group() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::A);
+        joypad.press(Button::Right);
+        joypad.press(Button::Down);
+
+        joypad.write_byte(0xff00, 0b0001_0000);
+        assert_eq!(joypad.read_byte(0xff00), 0b1101_1110);
+
+        joypad.write_byte(0xff00, 0b0010_0000);
+        assert_eq!(joypad.read_byte(0xff00), 0b1110_0110);
+
+        joypad.write_byte(0xff00, 0b0011_0000);
+        assert_eq!(joypad.read_byte(0xff00), 0b1111_1111);
+    }
+
+    #[test]
+    fn requests_interrupt_on_falling_edge() {
+        let mut joypad = Joypad::new();
+        joypad.write_byte(0xff00, 0b0001_0000);
+        assert_eq!(joypad.take_interrupts(), 0);
+
+        joypad.press(Button::B);
+        assert_eq!(joypad.take_interrupts(), 0b0001_0000);
+        assert_eq!(joypad.take_interrupts(), 0);
+
+        joypad.release(Button::B);
+        assert_eq!(joypad.take_interrupts(), 0);
+    }
+
+    #[test]
+    fn unselected_group_does_not_interrupt() {
+        let mut joypad = Joypad::new();
+        joypad.write_byte(0xff00, 0b0001_0000);
+        joypad.press(Button::Up);
+        assert_eq!(joypad.take_interrupts(), 0);
+
+        // Selecting a group with a key already held pulls its line low
+        joypad.write_byte(0xff00, 0b0010_0000);
+        assert_eq!(joypad.take_interrupts(), 0b0001_0000);
     }
 }
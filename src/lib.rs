@@ -21,8 +21,8 @@ macro_rules! decode_op {
             0x11 => { let am = $this.immediate_word(); $this.ld_de(am) },
             0x12 => { let am = $this.address_de(); $this.ld_mem_a(am); },
             0x13 => $this.inc_de(),
-            0x14 => { let d = $this.register_b(); $this.inc(d) }
-            0x15 => { let d = $this.register_b(); $this.dec(d) }
+            0x14 => { let d = $this.register_d(); $this.inc(d) }
+            0x15 => { let d = $this.register_d(); $this.dec(d) }
             0x16 => { let am = $this.immediate(); $this.ld_d(am); },
             0x17 => $this.rla(),
             0x18 => { let v = $this.immediate_signed(); $this.jr(v) },
@@ -194,11 +194,11 @@ macro_rules! decode_op {
             0xbe => { let val = $this.address_hl(); $this.cp(val); }
             0xbf => { let val = $this.register_a(); $this.cp(val); }
             0xc0 => $this.ret_nz(),
-            0xc1 => $this.pop_bc(),
+            0xc1 => { let am = $this.register_bc(); $this.pop(am) },
             0xc2 => { let loc = $this.immediate_word(); $this.jp_nz(loc); }
             0xc3 => { let loc = $this.immediate_word(); $this.jp(loc); }
             0xc4 => { let val = $this.immediate_word(); $this.call_nz(val); }
-            0xc5 => $this.push_bc(),
+            0xc5 => { let am = $this.register_bc(); $this.push(am) },
             0xc6 => { let val = $this.immediate(); $this.add_a(val); }
             0xc7 => $this.rst(0x00),
             0xc8 => $this.ret_z(),
@@ -210,53 +210,126 @@ macro_rules! decode_op {
             0xce => { let val = $this.immediate(); $this.adc_a(val); }
             0xcf => $this.rst(0x08),
             0xd0 => $this.ret_nc(),
-            0xd1 => $this.pop_de(),
+            0xd1 => { let am = $this.register_de(); $this.pop(am) },
             0xd2 => { let loc = $this.immediate_word(); $this.jp_nc(loc); }
-            0xd3 => {}
+            0xd3 => $this.illegal(0xd3),
             0xd4 => { let val = $this.immediate_word(); $this.call_nc(val); }
-            0xd5 => $this.push_de(),
+            0xd5 => { let am = $this.register_de(); $this.push(am) },
             0xd6 => { let val = $this.immediate(); $this.sub(val); }
             0xd7 => $this.rst(0x10),
             0xd8 => $this.ret_c(),
             0xd9 => $this.reti(),
             0xda => { let loc = $this.immediate_word(); $this.jp_c(loc); }
-            0xdb => {}
+            0xdb => $this.illegal(0xdb),
             0xdc => { let val = $this.immediate_word(); $this.call_c(val); }
-            0xdd => {}
+            0xdd => $this.illegal(0xdd),
             0xde => { let val = $this.immediate(); $this.sbc_a(val); }
             0xdf => $this.rst(0x18),
             0xe0 => { let loc = $this.immediate(); let val = $this.register_a(); $this.ldh_mem(loc, val); }
-            0xe1 => $this.pop_hl(),
+            0xe1 => { let am = $this.register_hl(); $this.pop(am) },
             0xe2 => { let loc = $this.address_c(); let val = $this.register_a(); $this.ld_mem(loc, val); }
-            0xe5 => $this.push_hl(),
+            0xe3 => $this.illegal(0xe3),
+            0xe4 => $this.illegal(0xe4),
+            0xe5 => { let am = $this.register_hl(); $this.push(am) },
             0xe6 => { let val = $this.immediate(); $this.and(val); }
             0xe7 => $this.rst(0x20),
             0xe8 => { let val = $this.immediate_signed(); $this.add_sp(val); }
             0xe9 => { let loc = $this.register_hl(); $this.jp(loc); }
             0xea => { let val = $this.immediate_word_address(); $this.ld_mem_a(val); }
+            0xeb => $this.illegal(0xeb),
+            0xec => $this.illegal(0xec),
+            0xed => $this.illegal(0xed),
             0xee => { let val = $this.immediate(); $this.xor(val); }
             0xef => $this.rst(0x28),
             0xf0 => { let val = $this.immediate(); $this.ldh_a(val); }
-            0xf1 => $this.pop_af(),
+            0xf1 => { let am = $this.register_af(); $this.pop(am) },
             0xf2 => { let val = $this.address_c(); $this.ld_a(val) }
             0xf3 => { $this.disable_interrupts() }
-            0xf5 => $this.push_af(),
+            0xf4 => $this.illegal(0xf4),
+            0xf5 => { let am = $this.register_af(); $this.push(am) },
             0xf6 => { let val = $this.immediate(); $this.or(val); }
             0xf7 => $this.rst(0x30),
             0xf8 => { $this.ld_hl_sp_plus_immediate_signed() }
             0xf9 => { let am = $this.register_hl(); $this.ld_sp(am); }
             0xfa => { let am = $this.immediate_word_address(); $this.ld_a(am); }
             0xfb => { $this.enable_interrupts(); }
+            0xfc => $this.illegal(0xfc),
+            0xfd => $this.illegal(0xfd),
             0xfe => { let val = $this.immediate(); $this.cp(val); }
             0xff => $this.rst(0x38),
-            _ => { panic!("Unknown OP: {:2x}", $op) }
         }
     }
 }
 macro_rules! decode_prefixed_op {
     ($op:expr, $this:ident) => {
         match $op {
+            0x00 => { let val = $this.register_b(); $this.rlc(val) }
+            0x01 => { let val = $this.register_c(); $this.rlc(val) }
+            0x02 => { let val = $this.register_d(); $this.rlc(val) }
+            0x03 => { let val = $this.register_e(); $this.rlc(val) }
+            0x04 => { let val = $this.register_h(); $this.rlc(val) }
+            0x05 => { let val = $this.register_l(); $this.rlc(val) }
+            0x06 => { let val = $this.address_hl(); $this.rlc(val) }
+            0x07 => { let val = $this.register_a(); $this.rlc(val) }
+            0x08 => { let val = $this.register_b(); $this.rrc(val) }
+            0x09 => { let val = $this.register_c(); $this.rrc(val) }
+            0x0a => { let val = $this.register_d(); $this.rrc(val) }
+            0x0b => { let val = $this.register_e(); $this.rrc(val) }
+            0x0c => { let val = $this.register_h(); $this.rrc(val) }
+            0x0d => { let val = $this.register_l(); $this.rrc(val) }
+            0x0e => { let val = $this.address_hl(); $this.rrc(val) }
+            0x0f => { let val = $this.register_a(); $this.rrc(val) }
+
+            0x10 => { let val = $this.register_b(); $this.rl(val) }
             0x11 => { let val = $this.register_c(); $this.rl(val) }
+            0x12 => { let val = $this.register_d(); $this.rl(val) }
+            0x13 => { let val = $this.register_e(); $this.rl(val) }
+            0x14 => { let val = $this.register_h(); $this.rl(val) }
+            0x15 => { let val = $this.register_l(); $this.rl(val) }
+            0x16 => { let val = $this.address_hl(); $this.rl(val) }
+            0x17 => { let val = $this.register_a(); $this.rl(val) }
+            0x18 => { let val = $this.register_b(); $this.rr(val) }
+            0x19 => { let val = $this.register_c(); $this.rr(val) }
+            0x1a => { let val = $this.register_d(); $this.rr(val) }
+            0x1b => { let val = $this.register_e(); $this.rr(val) }
+            0x1c => { let val = $this.register_h(); $this.rr(val) }
+            0x1d => { let val = $this.register_l(); $this.rr(val) }
+            0x1e => { let val = $this.address_hl(); $this.rr(val) }
+            0x1f => { let val = $this.register_a(); $this.rr(val) }
+
+            0x20 => { let val = $this.register_b(); $this.sla(val) }
+            0x21 => { let val = $this.register_c(); $this.sla(val) }
+            0x22 => { let val = $this.register_d(); $this.sla(val) }
+            0x23 => { let val = $this.register_e(); $this.sla(val) }
+            0x24 => { let val = $this.register_h(); $this.sla(val) }
+            0x25 => { let val = $this.register_l(); $this.sla(val) }
+            0x26 => { let val = $this.address_hl(); $this.sla(val) }
+            0x27 => { let val = $this.register_a(); $this.sla(val) }
+            0x28 => { let val = $this.register_b(); $this.sra(val) }
+            0x29 => { let val = $this.register_c(); $this.sra(val) }
+            0x2a => { let val = $this.register_d(); $this.sra(val) }
+            0x2b => { let val = $this.register_e(); $this.sra(val) }
+            0x2c => { let val = $this.register_h(); $this.sra(val) }
+            0x2d => { let val = $this.register_l(); $this.sra(val) }
+            0x2e => { let val = $this.address_hl(); $this.sra(val) }
+            0x2f => { let val = $this.register_a(); $this.sra(val) }
+
+            0x30 => { let val = $this.register_b(); $this.swap(val) }
+            0x31 => { let val = $this.register_c(); $this.swap(val) }
+            0x32 => { let val = $this.register_d(); $this.swap(val) }
+            0x33 => { let val = $this.register_e(); $this.swap(val) }
+            0x34 => { let val = $this.register_h(); $this.swap(val) }
+            0x35 => { let val = $this.register_l(); $this.swap(val) }
+            0x36 => { let val = $this.address_hl(); $this.swap(val) }
+            0x37 => { let val = $this.register_a(); $this.swap(val) }
+            0x38 => { let val = $this.register_b(); $this.srl(val) }
+            0x39 => { let val = $this.register_c(); $this.srl(val) }
+            0x3a => { let val = $this.register_d(); $this.srl(val) }
+            0x3b => { let val = $this.register_e(); $this.srl(val) }
+            0x3c => { let val = $this.register_h(); $this.srl(val) }
+            0x3d => { let val = $this.register_l(); $this.srl(val) }
+            0x3e => { let val = $this.address_hl(); $this.srl(val) }
+            0x3f => { let val = $this.register_a(); $this.srl(val) }
 
             0x40 => { let val = $this.register_b(); $this.bit(0, val) }
             0x41 => { let val = $this.register_c(); $this.bit(0, val) }
@@ -326,18 +399,160 @@ macro_rules! decode_prefixed_op {
             0x7e => { let val = $this.address_hl(); $this.bit(7, val) }
             0x7f => { let val = $this.register_a(); $this.bit(7, val) }
 
-            _ => panic!("Unimplemented prefixed op: {:2x}", $op)
+            0x80 => { let val = $this.register_b(); $this.res(0, val) }
+            0x81 => { let val = $this.register_c(); $this.res(0, val) }
+            0x82 => { let val = $this.register_d(); $this.res(0, val) }
+            0x83 => { let val = $this.register_e(); $this.res(0, val) }
+            0x84 => { let val = $this.register_h(); $this.res(0, val) }
+            0x85 => { let val = $this.register_l(); $this.res(0, val) }
+            0x86 => { let val = $this.address_hl(); $this.res(0, val) }
+            0x87 => { let val = $this.register_a(); $this.res(0, val) }
+            0x88 => { let val = $this.register_b(); $this.res(1, val) }
+            0x89 => { let val = $this.register_c(); $this.res(1, val) }
+            0x8a => { let val = $this.register_d(); $this.res(1, val) }
+            0x8b => { let val = $this.register_e(); $this.res(1, val) }
+            0x8c => { let val = $this.register_h(); $this.res(1, val) }
+            0x8d => { let val = $this.register_l(); $this.res(1, val) }
+            0x8e => { let val = $this.address_hl(); $this.res(1, val) }
+            0x8f => { let val = $this.register_a(); $this.res(1, val) }
+
+            0x90 => { let val = $this.register_b(); $this.res(2, val) }
+            0x91 => { let val = $this.register_c(); $this.res(2, val) }
+            0x92 => { let val = $this.register_d(); $this.res(2, val) }
+            0x93 => { let val = $this.register_e(); $this.res(2, val) }
+            0x94 => { let val = $this.register_h(); $this.res(2, val) }
+            0x95 => { let val = $this.register_l(); $this.res(2, val) }
+            0x96 => { let val = $this.address_hl(); $this.res(2, val) }
+            0x97 => { let val = $this.register_a(); $this.res(2, val) }
+            0x98 => { let val = $this.register_b(); $this.res(3, val) }
+            0x99 => { let val = $this.register_c(); $this.res(3, val) }
+            0x9a => { let val = $this.register_d(); $this.res(3, val) }
+            0x9b => { let val = $this.register_e(); $this.res(3, val) }
+            0x9c => { let val = $this.register_h(); $this.res(3, val) }
+            0x9d => { let val = $this.register_l(); $this.res(3, val) }
+            0x9e => { let val = $this.address_hl(); $this.res(3, val) }
+            0x9f => { let val = $this.register_a(); $this.res(3, val) }
+
+            0xa0 => { let val = $this.register_b(); $this.res(4, val) }
+            0xa1 => { let val = $this.register_c(); $this.res(4, val) }
+            0xa2 => { let val = $this.register_d(); $this.res(4, val) }
+            0xa3 => { let val = $this.register_e(); $this.res(4, val) }
+            0xa4 => { let val = $this.register_h(); $this.res(4, val) }
+            0xa5 => { let val = $this.register_l(); $this.res(4, val) }
+            0xa6 => { let val = $this.address_hl(); $this.res(4, val) }
+            0xa7 => { let val = $this.register_a(); $this.res(4, val) }
+            0xa8 => { let val = $this.register_b(); $this.res(5, val) }
+            0xa9 => { let val = $this.register_c(); $this.res(5, val) }
+            0xaa => { let val = $this.register_d(); $this.res(5, val) }
+            0xab => { let val = $this.register_e(); $this.res(5, val) }
+            0xac => { let val = $this.register_h(); $this.res(5, val) }
+            0xad => { let val = $this.register_l(); $this.res(5, val) }
+            0xae => { let val = $this.address_hl(); $this.res(5, val) }
+            0xaf => { let val = $this.register_a(); $this.res(5, val) }
+
+            0xb0 => { let val = $this.register_b(); $this.res(6, val) }
+            0xb1 => { let val = $this.register_c(); $this.res(6, val) }
+            0xb2 => { let val = $this.register_d(); $this.res(6, val) }
+            0xb3 => { let val = $this.register_e(); $this.res(6, val) }
+            0xb4 => { let val = $this.register_h(); $this.res(6, val) }
+            0xb5 => { let val = $this.register_l(); $this.res(6, val) }
+            0xb6 => { let val = $this.address_hl(); $this.res(6, val) }
+            0xb7 => { let val = $this.register_a(); $this.res(6, val) }
+            0xb8 => { let val = $this.register_b(); $this.res(7, val) }
+            0xb9 => { let val = $this.register_c(); $this.res(7, val) }
+            0xba => { let val = $this.register_d(); $this.res(7, val) }
+            0xbb => { let val = $this.register_e(); $this.res(7, val) }
+            0xbc => { let val = $this.register_h(); $this.res(7, val) }
+            0xbd => { let val = $this.register_l(); $this.res(7, val) }
+            0xbe => { let val = $this.address_hl(); $this.res(7, val) }
+            0xbf => { let val = $this.register_a(); $this.res(7, val) }
+
+            0xc0 => { let val = $this.register_b(); $this.set(0, val) }
+            0xc1 => { let val = $this.register_c(); $this.set(0, val) }
+            0xc2 => { let val = $this.register_d(); $this.set(0, val) }
+            0xc3 => { let val = $this.register_e(); $this.set(0, val) }
+            0xc4 => { let val = $this.register_h(); $this.set(0, val) }
+            0xc5 => { let val = $this.register_l(); $this.set(0, val) }
+            0xc6 => { let val = $this.address_hl(); $this.set(0, val) }
+            0xc7 => { let val = $this.register_a(); $this.set(0, val) }
+            0xc8 => { let val = $this.register_b(); $this.set(1, val) }
+            0xc9 => { let val = $this.register_c(); $this.set(1, val) }
+            0xca => { let val = $this.register_d(); $this.set(1, val) }
+            0xcb => { let val = $this.register_e(); $this.set(1, val) }
+            0xcc => { let val = $this.register_h(); $this.set(1, val) }
+            0xcd => { let val = $this.register_l(); $this.set(1, val) }
+            0xce => { let val = $this.address_hl(); $this.set(1, val) }
+            0xcf => { let val = $this.register_a(); $this.set(1, val) }
+
+            0xd0 => { let val = $this.register_b(); $this.set(2, val) }
+            0xd1 => { let val = $this.register_c(); $this.set(2, val) }
+            0xd2 => { let val = $this.register_d(); $this.set(2, val) }
+            0xd3 => { let val = $this.register_e(); $this.set(2, val) }
+            0xd4 => { let val = $this.register_h(); $this.set(2, val) }
+            0xd5 => { let val = $this.register_l(); $this.set(2, val) }
+            0xd6 => { let val = $this.address_hl(); $this.set(2, val) }
+            0xd7 => { let val = $this.register_a(); $this.set(2, val) }
+            0xd8 => { let val = $this.register_b(); $this.set(3, val) }
+            0xd9 => { let val = $this.register_c(); $this.set(3, val) }
+            0xda => { let val = $this.register_d(); $this.set(3, val) }
+            0xdb => { let val = $this.register_e(); $this.set(3, val) }
+            0xdc => { let val = $this.register_h(); $this.set(3, val) }
+            0xdd => { let val = $this.register_l(); $this.set(3, val) }
+            0xde => { let val = $this.address_hl(); $this.set(3, val) }
+            0xdf => { let val = $this.register_a(); $this.set(3, val) }
+
+            0xe0 => { let val = $this.register_b(); $this.set(4, val) }
+            0xe1 => { let val = $this.register_c(); $this.set(4, val) }
+            0xe2 => { let val = $this.register_d(); $this.set(4, val) }
+            0xe3 => { let val = $this.register_e(); $this.set(4, val) }
+            0xe4 => { let val = $this.register_h(); $this.set(4, val) }
+            0xe5 => { let val = $this.register_l(); $this.set(4, val) }
+            0xe6 => { let val = $this.address_hl(); $this.set(4, val) }
+            0xe7 => { let val = $this.register_a(); $this.set(4, val) }
+            0xe8 => { let val = $this.register_b(); $this.set(5, val) }
+            0xe9 => { let val = $this.register_c(); $this.set(5, val) }
+            0xea => { let val = $this.register_d(); $this.set(5, val) }
+            0xeb => { let val = $this.register_e(); $this.set(5, val) }
+            0xec => { let val = $this.register_h(); $this.set(5, val) }
+            0xed => { let val = $this.register_l(); $this.set(5, val) }
+            0xee => { let val = $this.address_hl(); $this.set(5, val) }
+            0xef => { let val = $this.register_a(); $this.set(5, val) }
+
+            0xf0 => { let val = $this.register_b(); $this.set(6, val) }
+            0xf1 => { let val = $this.register_c(); $this.set(6, val) }
+            0xf2 => { let val = $this.register_d(); $this.set(6, val) }
+            0xf3 => { let val = $this.register_e(); $this.set(6, val) }
+            0xf4 => { let val = $this.register_h(); $this.set(6, val) }
+            0xf5 => { let val = $this.register_l(); $this.set(6, val) }
+            0xf6 => { let val = $this.address_hl(); $this.set(6, val) }
+            0xf7 => { let val = $this.register_a(); $this.set(6, val) }
+            0xf8 => { let val = $this.register_b(); $this.set(7, val) }
+            0xf9 => { let val = $this.register_c(); $this.set(7, val) }
+            0xfa => { let val = $this.register_d(); $this.set(7, val) }
+            0xfb => { let val = $this.register_e(); $this.set(7, val) }
+            0xfc => { let val = $this.register_h(); $this.set(7, val) }
+            0xfd => { let val = $this.register_l(); $this.set(7, val) }
+            0xfe => { let val = $this.address_hl(); $this.set(7, val) }
+            0xff => { let val = $this.register_a(); $this.set(7, val) }
         }
     }
 }
 
+pub mod boot;
+pub mod bus;
 pub mod cartridge;
 pub mod cpu;
 pub mod debugger;
 pub mod disasm;
+pub mod error;
+pub mod gpu;
+pub mod joypad;
+pub mod memory_map;
 pub mod mmu;
-mod gpu;
-mod joypad;
+pub mod png;
+pub mod rewind;
+mod interrupt;
 mod timer;
-mod memory_map;
+mod serial;
+mod state;
 mod data;
@@ -1,36 +1,179 @@
 #![allow(dead_code)]
 extern crate gbrs;
 
+use gbrs::boot::Model;
 use gbrs::debugger::Debugger;
 use gbrs::cpu::CPU;
 use gbrs::disasm::Disassembler;
+use gbrs::gpu::{Palette, SCREEN_HEIGHT, SCREEN_WIDTH};
 use gbrs::mmu::MMU;
 use gbrs::cartridge::Cartridge;
-use std::convert::AsRef;
+use gbrs::error::EmulatorError;
+use gbrs::png;
 use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
+
+/// How often battery-backed RAM is written out while the game runs
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Frames run by `headless` unless `--frames` says otherwise
+const HEADLESS_FRAMES: u64 = 60;
+
+/// Cycles the GPU takes to draw a frame, 154 lines of 456
+const FRAME_CYCLES: u64 = 154 * 456;
 
 fn main() {
     let args: Vec<_> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("Usage: {} <run|headless|disasm|info> <rom> [options]", args[0]);
+        eprintln!("  --state <file>     Start from a save state (run, headless)");
+        eprintln!("  --boot-rom <file>  Run a 256 byte boot ROM dump instead of the built in one (run, headless)");
+        eprintln!("  --skip-boot        Start the cartridge at 0100 with post-boot registers (run, headless)");
+        eprintln!("  --model <name>     dmg, mgb or cgb registers for --skip-boot, dmg by default (run, headless)");
+        eprintln!("  --frames <n>       Frames to run before the screenshot (headless)");
+        eprintln!("  --until-pc <addr>  Stop early when PC reaches a hex address (headless)");
+        eprintln!("  --palette <name>   gray, dmg or pocket (headless)");
+        eprintln!("  --output <file>    Screenshot path, the ROM's name with .png by default (headless)");
+        process::exit(2);
+    }
+
+    if let Err(error) = start(&args[1], &args[2], &args[3..]) {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
+}
+
+/// Returns the value following `--name` in `options`
+fn option<'a>(options: &'a [String], name: &str) -> Option<&'a str> {
+    options.iter()
+        .position(|option| option == name)
+        .and_then(|i| options.get(i + 1))
+        .map(|value| value.as_ref())
+}
 
-    let cart = Cartridge::load(&args[2]);
+/// Whether the flag `--name` was passed
+fn flag(options: &[String], name: &str) -> bool {
+    options.iter().any(|option| option == name)
+}
+
+/// Parses an option's value with `parse`, exiting with a usage error if it's invalid
+fn parse_option<T, F: Fn(&str) -> Option<T>>(options: &[String], name: &str, parse: F) -> Option<T> {
+    option(options, name).map(|value| {
+        parse(value).unwrap_or_else(|| {
+            eprintln!("Invalid value for {}: {}", name, value);
+            process::exit(2);
+        })
+    })
+}
+
+fn start(command: &str, filename: &str, options: &[String]) -> Result<(), EmulatorError> {
+    let cart = Cartridge::load(filename)?;
     let size = cart.size();
     let mut mmu: MMU = MMU::new();
     mmu.load_cartridge(cart);
 
-    match args[1].as_ref() {
+    match command {
         "run" => {
             println!("Loading ROM and beginning emulation");
-            let mut debugger = Debugger::new();
-            let mut cpu: CPU = CPU::new(mmu);
+            let mut cpu = power_on(mmu, options)?;
+            let result = run(&mut cpu);
 
-            while !cpu.stopped {
-                cpu.step(&mut debugger);
+            // Keep the save even if emulation failed
+            if let Err(error) = cpu.mmu.cartridge.save() {
+                println!("Failed to write save file: {}", error);
+            }
+            result?;
+        }
+        "headless" => {
+            let frames = parse_option(options, "--frames", |n| n.parse().ok()).unwrap_or(HEADLESS_FRAMES);
+            let until_pc = parse_option(options, "--until-pc", |pc| u16::from_str_radix(pc, 16).ok());
+            let palette = parse_option(options, "--palette", Palette::from_name).unwrap_or(Palette::GRAYSCALE);
+            let output = option(options, "--output").map(|output| output.to_string())
+                .unwrap_or_else(|| Path::new(filename).with_extension("png").to_string_lossy().into_owned());
+
+            let mut cpu = power_on(mmu, options)?;
+            let start_frame = cpu.mmu.gpu.frame_count();
+            let result = run_headless(&mut cpu, frames, until_pc);
+
+            // A screenshot of a crashed game is still worth having
+            let image = png::encode(SCREEN_WIDTH, SCREEN_HEIGHT, &palette.apply(cpu.mmu.gpu.framebuffer()));
+            fs::write(&output, image)?;
+            let frames_run = cpu.mmu.gpu.frame_count() - start_frame;
+            println!("Ran {} frames, stopped at pc = {:04x}, wrote {}", frames_run, cpu.registers.pc, output);
+            if !result? {
+                eprintln!("Error: timed out after {} frames' worth of cycles with {} frames drawn, is the LCD off?",
+                          frames, frames_run);
+                process::exit(1);
             }
         }
         "disasm" => {
             let mut disasm = Disassembler::new(mmu);
             disasm.disassemble(size);
         }
+        "info" => {
+            println!("{}", mmu.cartridge.header());
+            println!("File size:        {} bytes", size);
+        }
         _ => {}
     }
+    Ok(())
+}
+
+/// Sets up the CPU to boot, skip booting or resume a save state as `options` say
+fn power_on(mut mmu: MMU, options: &[String]) -> Result<CPU, EmulatorError> {
+    let model = parse_option(options, "--model", Model::from_name);
+    if model.is_some() && !flag(options, "--skip-boot") {
+        eprintln!("--model only applies with --skip-boot");
+        process::exit(2);
+    }
+    if let Some(boot_rom) = option(options, "--boot-rom") {
+        mmu.load_boot_rom(&fs::read(boot_rom)?)?;
+    }
+    let mut cpu: CPU = CPU::new(mmu);
+    if flag(options, "--skip-boot") {
+        cpu.skip_boot(model.unwrap_or(Model::Dmg));
+    }
+    if let Some(state) = option(options, "--state") {
+        cpu.load_state(&fs::read(state)?)?;
+    }
+    Ok(cpu)
+}
+
+/// Runs until the debugger exits or the CPU fails
+fn run(cpu: &mut CPU) -> Result<(), EmulatorError> {
+    let mut debugger = Debugger::new();
+    let mut last_save = Instant::now();
+
+    while !debugger.exit_requested() {
+        cpu.step(&mut debugger)?;
+
+        if last_save.elapsed() >= SAVE_INTERVAL {
+            if let Err(error) = cpu.mmu.cartridge.flush() {
+                println!("Failed to write save file: {}", error);
+            }
+            last_save = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+/// Runs for `frames` frames, or until PC reaches `until_pc`, without the
+/// interactive debugger. Frames aren't counted while the LCD is off, so this
+/// gives up after as many cycles as `frames` frames take, returning false.
+fn run_headless(cpu: &mut CPU, frames: u64, until_pc: Option<u16>) -> Result<bool, EmulatorError> {
+    let mut debugger = Debugger::disabled();
+    let end = cpu.mmu.gpu.frame_count() + frames;
+    // One frame to spare for the boot ROM, which starts with the LCD off
+    let timeout = cpu.cycles() + (frames + 1) * FRAME_CYCLES;
+
+    while cpu.mmu.gpu.frame_count() < end && Some(cpu.registers.pc) != until_pc {
+        if cpu.cycles() >= timeout {
+            return Ok(false);
+        }
+        cpu.step(&mut debugger)?;
+    }
+    Ok(true)
 }
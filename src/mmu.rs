@@ -1,8 +1,13 @@
+use boot::{self, Model};
+use bus::Bus;
 use cartridge::Cartridge;
+use error::EmulatorError;
 use std::fmt;
 use gpu;
 use memory_map::{ReadByte, WriteByte};
 use joypad;
+use serial;
+use state::{Snapshot, StateReader, StateWriter};
 use timer;
 
 const BIOS: [u8; 0x100] = [
@@ -31,46 +36,91 @@ pub struct MMU {
     pub gpu: gpu::GPU,
     pub joypad: joypad::Joypad,
     pub timer: timer::Timer,
+    pub serial: serial::Serial,
     pub ie: u8,
     pub interrupt_flag: u8,
-    bootroom_enabled: bool
+    bootroom_enabled: bool,
+    boot_rom: [u8; boot::BOOT_ROM_SIZE]
 }
 
 impl MMU {
     pub fn new() -> MMU {
         MMU {
-            cartridge: Cartridge::new(Box::new([])),
+            cartridge: Cartridge::empty(),
             working_ram: [0; 0x2000],
             hram: [0; 127],
             gpu: gpu::GPU::new(),
             joypad: joypad::Joypad::new(),
             timer: timer::Timer::new(),
+            serial: serial::Serial::new(),
             ie: 0,
             interrupt_flag: 0,
-            bootroom_enabled: true
+            bootroom_enabled: true,
+            boot_rom: BIOS
         }
     }
 
+    /// Runs `rom` at power on in place of the built in DMG boot ROM
+    pub fn load_boot_rom(&mut self, rom: &[u8]) -> Result<(), EmulatorError> {
+        if rom.len() != boot::BOOT_ROM_SIZE {
+            return Err(EmulatorError::BootRomSize(rom.len()));
+        }
+        self.boot_rom.copy_from_slice(rom);
+        Ok(())
+    }
+
+    /// Unmaps the boot ROM and sets up I/O as `model`'s boot ROM leaves it
+    pub fn skip_boot(&mut self, model: Model) {
+        for &(address, value) in boot::POST_BOOT_IO.iter().chain(model.post_boot_io()) {
+            self.write_byte(address, value);
+        }
+        self.timer.set_divider(model.post_boot_divider());
+        self.bootroom_enabled = false;
+    }
+
     pub fn load_cartridge(&mut self, cartridge: Cartridge) {
         self.cartridge = cartridge;
     }
 
     pub fn step(&mut self, clock: u8) {
-        self.gpu.step(clock);
-        self.timer.step(clock);
+        self.interrupt_flag |= self.gpu.step(clock);
+        self.interrupt_flag |= self.timer.step(clock);
+        self.interrupt_flag |= self.serial.step(clock);
+        self.interrupt_flag |= self.joypad.take_interrupts();
     }
+}
+
+impl Bus for MMU {
+    fn tick(&mut self, cycles: u8) {
+        self.step(cycles);
+    }
+}
 
-    pub fn read_word(&self, address: u16) -> u16 {
-        let lower = self.read_byte(address) as u16;
-        let upper = (self.read_byte(address + 1) as u16) << 8;
-        upper | lower
+impl Snapshot for MMU {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.working_ram);
+        writer.bytes(&self.hram);
+        writer.u8(self.ie);
+        writer.u8(self.interrupt_flag);
+        writer.bool(self.bootroom_enabled);
+        self.gpu.write_state(writer);
+        self.timer.write_state(writer);
+        self.serial.write_state(writer);
+        self.joypad.write_state(writer);
+        self.cartridge.write_state(writer);
     }
 
-    pub fn write_word(&mut self, address: u16, value: u16) {
-        let lower = (value & 0xff) as u8;
-        let upper = ((value & 0xff00) >> 8) as u8;
-        self.write_byte(address, lower);
-        self.write_byte(address + 1, upper);
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        reader.bytes(&mut self.working_ram)?;
+        reader.bytes(&mut self.hram)?;
+        self.ie = reader.u8()?;
+        self.interrupt_flag = reader.u8()?;
+        self.bootroom_enabled = reader.bool()?;
+        self.gpu.read_state(reader)?;
+        self.timer.read_state(reader)?;
+        self.serial.read_state(reader)?;
+        self.joypad.read_state(reader)?;
+        self.cartridge.read_state(reader)
     }
 }
 
@@ -79,7 +129,7 @@ impl ReadByte for MMU {
         match address {
             0x0000...0x00ff => {                                                  // Bootstrap ROM or cartridge
                 if self.bootroom_enabled {
-                    BIOS[address as usize]
+                    self.boot_rom[address as usize]
                 } else {
                     self.cartridge.read_byte(address)
                 }
@@ -94,10 +144,10 @@ impl ReadByte for MMU {
 
             // Hardware I/O ports
             0xff00          => { self.joypad.read_byte(address) }                 // P1
-            0xff01...0xff02 => { println!("Serial: {:04x}", address); 0 }         // Serial data transfer
-            0xff03          => { println!("Unknown: {:04x}", address); 0 }
+            0xff01...0xff02 => { self.serial.read_byte(address) }                 // Serial data transfer
+            0xff03          => { 0xff }                                           // Unmapped
             0xff04...0xff07 => { self.timer.read_byte(address) }                  // Timer and divider
-            0xff08...0xff0e => { println!("Reading I/O: {:2x}", address); 0}      // Memory-mapped I/O
+            0xff08...0xff0e => { 0xff }                                           // Unmapped
             0xff0f => { self.interrupt_flag }
             0xff10...0xff3f => { 0 } // Sound
             0xff40...0xff4b => { self.gpu.read_byte(address) }                    // GPU
@@ -105,7 +155,6 @@ impl ReadByte for MMU {
 
             0xff80...0xfffe => { self.hram[(address & 0x7f) as usize] }           // Zero-page RAM (High RAM, HRAM)
             0xffff          => { self.ie }                                                 // Interrupt enable register
-            _ => { panic!("Read memory out of bounds: {}", address) }
         }
     }
 }
@@ -122,10 +171,10 @@ impl WriteByte for MMU {
             0xfe00...0xfe9f => { self.gpu.write_byte(address, value); }                       // Sprite info
             0xfea0...0xfeff => { }                                                            // Unusable
             0xff00          => { self.joypad.write_byte(address, value); }                    // P1
-            0xff01...0xff02 => { println!("Serial: {:04x} = {:02x}", address, value); }       // Serial data transfer
-            0xff03          => { println!("Unknown: {:04x} = {:02x}", address, value); }
+            0xff01...0xff02 => { self.serial.write_byte(address, value); }                    // Serial data transfer
+            0xff03          => { }                                                            // Unmapped
             0xff04...0xff07 => { self.timer.write_byte(address, value); }                     // Timer and divider
-            0xff08...0xff0e => { println!("Writing I/O: {:2x} = {:2x}", address, value); }    // Memory-mapped I/O
+            0xff08...0xff0e => { }                                                            // Unmapped
             0xff0f => { self.interrupt_flag = value; }
             0xff10...0xff3f => { } // Sound
             0xff46 => { // DMA
@@ -144,7 +193,6 @@ impl WriteByte for MMU {
             0xff51...0xff7f => { }                                                            // Unusable
             0xff80...0xfffe => { self.hram[(address & 0x7f) as usize] = value; }              // Zero-page RAM (High RAM, HRAM)
             0xffff          => { self.ie = value; }                                                    // Interrupt enable register
-            _ => { panic!("Wrote memory out of bounds: {:2x}", address); }
         }
     }
 
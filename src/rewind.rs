@@ -0,0 +1,176 @@
+use std::collections::VecDeque;
+use cpu::CPU;
+use error::EmulatorError;
+use state::{StateReader, StateWriter};
+
+/// Frames kept by a debugger's rewind buffer, ten seconds of play
+pub const DEFAULT_CAPACITY: usize = 600;
+
+/// A ring buffer of save states taken at the start of each frame, so the
+/// machine can be stepped backwards.
+///
+/// Only the newest state is kept whole. Every older one is stored as its
+/// difference from the state after it: the two are XORed and the runs of
+/// zeros, where a frame left memory untouched, are skipped. Restoring
+/// walks back from the newest state, so dropping the oldest frame when the
+/// buffer is full never invalidates the others.
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    last_frame: Option<u64>
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity: capacity.max(1),
+            latest: None,
+            deltas: VecDeque::new(),
+            last_frame: None
+        }
+    }
+
+    /// Number of frames that can be restored
+    pub fn len(&self) -> usize {
+        self.latest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Takes a snapshot if the GPU has started a new frame since the last
+    /// call. Must be called between instructions.
+    pub fn record(&mut self, cpu: &CPU) {
+        let frame = cpu.mmu.gpu.frame_count();
+        if self.last_frame != Some(frame) {
+            self.last_frame = Some(frame);
+            self.push(cpu.save_state());
+        }
+    }
+
+    /// Adds a state as the newest frame, dropping the oldest if full
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode(&state, &previous));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /// Returns the state from the start of the frame `frames` before the
+    /// newest one, where 0 is the newest. Everything after it is discarded,
+    /// so it becomes the newest frame and rewinding again goes further back.
+    pub fn rewind(&mut self, frames: usize) -> Option<Vec<u8>> {
+        if frames >= self.len() {
+            return None;
+        }
+        let mut state = self.latest.take().unwrap();
+        for _ in 0..frames {
+            let delta = self.deltas.pop_back().unwrap();
+            state = decode(&state, &delta).expect("Rewind buffer is corrupt");
+        }
+        self.latest = Some(state.clone());
+        Some(state)
+    }
+}
+
+/// Encodes `target` relative to `base`: its length, then a series of
+/// unchanged byte counts each followed by a run of XORed changed bytes
+fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let byte = |data: &[u8], i: usize| data.get(i).cloned().unwrap_or(0);
+    let mut writer = StateWriter::new();
+    writer.u32(target.len() as u32);
+
+    let mut i = 0;
+    while i < target.len() {
+        let start = i;
+        while i < target.len() && byte(base, i) == target[i] {
+            i += 1;
+        }
+        let unchanged = i - start;
+        let start = i;
+        while i < target.len() && byte(base, i) != target[i] {
+            i += 1;
+        }
+        writer.u32(unchanged as u32);
+        writer.u32((i - start) as u32);
+        for (offset, &value) in target[start..i].iter().enumerate() {
+            writer.u8(byte(base, start + offset) ^ value);
+        }
+    }
+    writer.into_inner()
+}
+
+fn decode(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, EmulatorError> {
+    let mut reader = StateReader::new(delta);
+    let length = reader.u32()? as usize;
+    let mut target = base.to_vec();
+    target.resize(length, 0);
+
+    let mut i = 0;
+    while !reader.is_empty() {
+        i += reader.u32()? as usize;
+        let changed = reader.u32()? as usize;
+        if i + changed > length {
+            return Err(EmulatorError::CorruptState);
+        }
+        for byte in &mut target[i..i + changed] {
+            *byte ^= reader.u8()?;
+        }
+        i += changed;
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, Rewind};
+
+    #[test]
+    fn delta_round_trip() {
+        let base = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        let target = vec![1, 2, 9, 4, 5, 0, 0, 8];
+        let delta = encode(&base, &target);
+        assert_eq!(decode(&base, &delta).unwrap(), target);
+
+        // Unchanged states compress to almost nothing
+        assert!(encode(&base, &base).len() < 16);
+
+        let longer = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        assert_eq!(decode(&base, &encode(&base, &longer)).unwrap(), longer);
+        assert_eq!(decode(&longer, &encode(&longer, &base)).unwrap(), base);
+    }
+
+    #[test]
+    fn rewinds_to_earlier_frames() {
+        let mut rewind = Rewind::new(10);
+        assert_eq!(rewind.rewind(0), None);
+        for frame in 0..5u8 {
+            rewind.push(vec![frame; 64]);
+        }
+        assert_eq!(rewind.len(), 5);
+        assert_eq!(rewind.rewind(5), None);
+        assert_eq!(rewind.rewind(0), Some(vec![4; 64]));
+        assert_eq!(rewind.rewind(2), Some(vec![2; 64]));
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.rewind(1), Some(vec![1; 64]));
+
+        rewind.push(vec![7; 64]);
+        assert_eq!(rewind.rewind(1), Some(vec![1; 64]));
+    }
+
+    #[test]
+    fn drops_the_oldest_frames() {
+        let mut rewind = Rewind::new(3);
+        for frame in 0..5u8 {
+            rewind.push(vec![frame; 64]);
+        }
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.rewind(2), Some(vec![2; 64]));
+        assert_eq!(rewind.rewind(1), None);
+    }
+}
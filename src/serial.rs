@@ -0,0 +1,190 @@
+use error::EmulatorError;
+use interrupt::Interrupt;
+use memory_map::{ReadByte, WriteByte};
+use state::{Snapshot, StateReader, StateWriter};
+
+/// Cycles per bit with the internal 8192 Hz clock
+const BIT_CYCLES: u16 = 512;
+
+const TRANSFER_START: u8 = 0b1000_0000;
+const INTERNAL_CLOCK: u8 = 0b0000_0001;
+
+/// The link port. Nothing is ever plugged in, so every byte sent receives
+/// 0xff, and transfers waiting on an external clock never finish.
+pub struct Serial {
+    data: u8,
+    transferring: bool,
+    internal_clock: bool,
+    // Bits still to shift out of the current transfer, and cycles until the next one
+    bits: u8,
+    clock: u16,
+    // The byte being sent, since SB fills up with what's received
+    sending: u8,
+    output: Vec<u8>,
+    callback: Option<Box<dyn FnMut(u8)>>
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: 0,
+            transferring: false,
+            internal_clock: false,
+            bits: 0,
+            clock: 0,
+            sending: 0,
+            output: vec![],
+            callback: None
+        }
+    }
+
+    /// Every byte sent since power on, or since the last `take_output`
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.output.split_off(0)
+    }
+
+    /// Calls `callback` with each byte as its transfer completes
+    pub fn set_callback(&mut self, callback: Box<dyn FnMut(u8)>) {
+        self.callback = Some(callback);
+    }
+
+    /// Advances the transfer by `clock` cycles, returning the interrupts it
+    /// requested as IF bits
+    pub fn step(&mut self, clock: u8) -> u8 {
+        if !self.transferring || !self.internal_clock {
+            return 0;
+        }
+
+        self.clock += clock as u16;
+        while self.clock >= BIT_CYCLES && self.bits > 0 {
+            self.clock -= BIT_CYCLES;
+            self.data = (self.data << 1) | 1;
+            self.bits -= 1;
+        }
+        if self.bits > 0 {
+            return 0;
+        }
+
+        self.transferring = false;
+        self.output.push(self.sending);
+        if let Some(ref mut callback) = self.callback {
+            callback(self.sending);
+        }
+        Interrupt::Serial.mask()
+    }
+}
+
+impl Snapshot for Serial {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.u8(self.data);
+        writer.u8(self.read_byte(0xff02));
+        writer.u8(self.bits);
+        writer.u16(self.clock);
+        writer.u8(self.sending);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.data = reader.u8()?;
+        let control = reader.u8()?;
+        self.transferring = control & TRANSFER_START == TRANSFER_START;
+        self.internal_clock = control & INTERNAL_CLOCK == INTERNAL_CLOCK;
+        self.bits = reader.u8()?;
+        self.clock = reader.u16()?;
+        self.sending = reader.u8()?;
+        Ok(())
+    }
+}
+
+impl ReadByte for Serial {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0xff01 => { self.data }
+            0xff02 => {
+                // Unused bits read 1
+                let mut value = 0b0111_1110;
+                if self.transferring { value |= TRANSFER_START; }
+                if self.internal_clock { value |= INTERNAL_CLOCK; }
+                value
+            }
+            _ => { 0xff }
+        }
+    }
+}
+
+impl WriteByte for Serial {
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address {
+            0xff01 => { self.data = value; }
+            0xff02 => {
+                self.transferring = value & TRANSFER_START == TRANSFER_START;
+                self.internal_clock = value & INTERNAL_CLOCK == INTERNAL_CLOCK;
+                if self.transferring {
+                    self.sending = self.data;
+                    self.bits = 8;
+                    self.clock = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use memory_map::{ReadByte, WriteByte};
+    use super::Serial;
+
+    fn step(serial: &mut Serial, cycles: usize) -> u8 {
+        (0..cycles / 4).fold(0, |interrupts, _| interrupts | serial.step(4))
+    }
+
+    #[test]
+    fn internal_clock_transfer_takes_4096_cycles() {
+        let mut serial = Serial::new();
+        serial.write_byte(0xff01, b'P');
+        serial.write_byte(0xff02, 0x81);
+        assert_eq!(serial.read_byte(0xff02), 0xff);
+
+        assert_eq!(step(&mut serial, 4092), 0);
+        assert_eq!(serial.read_byte(0xff02) & 0x80, 0x80);
+        assert_eq!(serial.output(), b"");
+
+        assert_eq!(step(&mut serial, 4), 0b1000);
+        assert_eq!(serial.read_byte(0xff02), 0x7f);
+        // Nothing is connected, so only ones were shifted in
+        assert_eq!(serial.read_byte(0xff01), 0xff);
+        assert_eq!(serial.take_output(), b"P".to_vec());
+        assert_eq!(serial.output(), b"");
+    }
+
+    #[test]
+    fn external_clock_transfer_never_finishes() {
+        let mut serial = Serial::new();
+        serial.write_byte(0xff01, 0x12);
+        serial.write_byte(0xff02, 0x80);
+        assert_eq!(step(&mut serial, 10000), 0);
+        assert_eq!(serial.read_byte(0xff02), 0xfe);
+        assert_eq!(serial.read_byte(0xff01), 0x12);
+    }
+
+    #[test]
+    fn calls_callback_with_sent_bytes() {
+        let sent = Rc::new(RefCell::new(vec![]));
+        let mut serial = Serial::new();
+        let log = sent.clone();
+        serial.set_callback(Box::new(move |byte| log.borrow_mut().push(byte)));
+
+        for &byte in b"ok" {
+            serial.write_byte(0xff01, byte);
+            serial.write_byte(0xff02, 0x81);
+            step(&mut serial, 4096);
+        }
+        assert_eq!(*sent.borrow(), b"ok".to_vec());
+    }
+}
@@ -0,0 +1,152 @@
+use error::EmulatorError;
+
+/// Identifies a save state file
+pub const MAGIC: &[u8; 4] = b"GBRS";
+
+/// Bumped whenever the layout of any component's state changes, so states
+/// from other builds are rejected rather than misread
+pub const VERSION: u32 = 5;
+
+/// A component whose complete state can be written to and restored from a
+/// save state. Fields are written in a fixed order with no framing, so
+/// `read_state` must mirror `write_state` exactly.
+pub trait Snapshot {
+    fn write_state(&self, writer: &mut StateWriter);
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError>;
+}
+
+/// Appends little-endian values to a state blob
+pub struct StateWriter {
+    data: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { data: vec![] }
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+}
+
+/// Reads values back in the order a `StateWriter` wrote them
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data, position: 0 }
+    }
+
+    /// Whether every byte has been read
+    pub fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], EmulatorError> {
+        if self.data.len() - self.position < length {
+            return Err(EmulatorError::CorruptState);
+        }
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, EmulatorError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, EmulatorError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, EmulatorError> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, EmulatorError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, EmulatorError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Fills `value` entirely, so a state for a differently sized memory fails
+    /// further on rather than shifting every later field
+    pub fn bytes(&mut self, value: &mut [u8]) -> Result<(), EmulatorError> {
+        value.copy_from_slice(self.take(value.len())?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use error::EmulatorError;
+    use super::{StateReader, StateWriter};
+
+    #[test]
+    fn round_trip() {
+        let mut writer = StateWriter::new();
+        writer.u8(0x12);
+        writer.bool(true);
+        writer.u16(0x3456);
+        writer.u32(0x789a_bcde);
+        writer.u64(0x0102_0304_0506_0708);
+        writer.bytes(&[1, 2, 3]);
+        let data = writer.into_inner();
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(reader.u8().unwrap(), 0x12);
+        assert!(reader.bool().unwrap());
+        assert_eq!(reader.u16().unwrap(), 0x3456);
+        assert_eq!(reader.u32().unwrap(), 0x789a_bcde);
+        assert_eq!(reader.u64().unwrap(), 0x0102_0304_0506_0708);
+        let mut bytes = [0; 3];
+        reader.bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2, 3]);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn truncated_data_is_an_error() {
+        let mut reader = StateReader::new(&[0x12]);
+        match reader.u16() {
+            Err(EmulatorError::CorruptState) => {}
+            result => panic!("Expected a corrupt state error, got {:?}", result)
+        }
+    }
+}
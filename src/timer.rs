@@ -1,4 +1,7 @@
+use error::EmulatorError;
+use interrupt::Interrupt;
 use memory_map::{ReadByte, WriteByte};
+use state::{Snapshot, StateReader, StateWriter};
 
 enum ClockFrequency {
     Hz4096,
@@ -7,85 +10,129 @@ enum ClockFrequency {
     Hz16384
 }
 
+impl ClockFrequency {
+    fn from_tac(value: u8) -> ClockFrequency {
+        match value & 0b0011 {
+            0b00 => ClockFrequency::Hz4096,
+            0b01 => ClockFrequency::Hz262144,
+            0b10 => ClockFrequency::Hz65536,
+            _ => ClockFrequency::Hz16384
+        }
+    }
+
+    /// The bit of the internal divider whose falling edge clocks TIMA
+    fn divider_bit(&self) -> u16 {
+        match *self {
+            ClockFrequency::Hz4096 => 1 << 9,
+            ClockFrequency::Hz262144 => 1 << 3,
+            ClockFrequency::Hz65536 => 1 << 5,
+            ClockFrequency::Hz16384 => 1 << 7
+        }
+    }
+}
+
+/// Cycles between TIMA overflowing and being reloaded from TMA, during which
+/// it reads as zero
+const RELOAD_DELAY: u8 = 4;
+
 pub struct Timer {
-    div: u8,
+    divider: u16,
     tima: u8,
     tma: u8,
-    tac: u8,
-    stop: bool,
+    enabled: bool,
     input_clock: ClockFrequency,
-    internal_clock: u8,
-    sub: u8
+    reload_delay: u8
 }
 
 impl Timer {
     pub fn new() -> Timer {
         Timer {
-            div: 0,
+            divider: 0,
             tima: 0,
             tma: 0,
-            tac: 0,
-            stop: false,
+            enabled: false,
             input_clock: ClockFrequency::Hz4096,
-            internal_clock: 0,
-            sub: 0
+            reload_delay: 0
         }
     }
 
-    pub fn step(&mut self, clock: u8) {
-        self.sub = self.sub + clock;
-        if self.sub > 15 {
-            self.internal_clock = self.internal_clock + 1;
-            self.sub = self.sub - 16;
-        }
+    /// Sets the internal divider, whose upper byte is DIV, without the
+    /// side effects of writing DIV
+    pub fn set_divider(&mut self, divider: u16) {
+        self.divider = divider;
+    }
 
-        if !self.stop {
-            match self.input_clock {
-                ClockFrequency::Hz4096 => {
-                    if self.internal_clock >= 64 {
-                        self.inc();
-                    }
-                }
-                ClockFrequency::Hz262144 => {
-                    if self.internal_clock >= 1 {
-                        self.inc();
-                    }
-                }
-                ClockFrequency::Hz65536 => {
-                    if self.internal_clock >= 4 {
-                        self.inc();
-                    }
-                }
-                ClockFrequency::Hz16384 => {
-                    if self.internal_clock >= 16 {
-                        self.inc();
-                    }
+    /// Advances the timer by `clock` cycles, returning the interrupts it
+    /// requested as IF bits
+    pub fn step(&mut self, clock: u8) -> u8 {
+        let mut interrupts = 0;
+
+        for _ in 0..clock {
+            if self.reload_delay > 0 {
+                self.reload_delay -= 1;
+                if self.reload_delay == 0 {
+                    self.tima = self.tma;
+                    interrupts |= Interrupt::Timer.mask();
                 }
             }
+
+            let before = self.timer_signal();
+            self.divider = self.divider.wrapping_add(1);
+            if before && !self.timer_signal() {
+                self.inc();
+            }
         }
+
+        interrupts
+    }
+
+    /// The input to TIMA's falling edge detector: the selected divider bit,
+    /// gated by the enable bit in TAC
+    fn timer_signal(&self) -> bool {
+        self.enabled && self.divider & self.input_clock.divider_bit() != 0
     }
 
     fn inc(&mut self) {
-        self.tima += 1;
-        self.internal_clock = 0;
-        if self.tima == 255 {
-            self.tima = self.tma;
-            // return INTERRUPT
-            return
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.reload_delay = RELOAD_DELAY;
         }
-        self.tima += 1;
+    }
+}
+
+impl Snapshot for Timer {
+    fn write_state(&self, writer: &mut StateWriter) {
+        writer.u16(self.divider);
+        writer.u8(self.tima);
+        writer.u8(self.tma);
+        // TAC holds the enable bit and input clock
+        writer.u8(self.read_byte(0xff07));
+        writer.u8(self.reload_delay);
+    }
+
+    fn read_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.divider = reader.u16()?;
+        self.tima = reader.u8()?;
+        self.tma = reader.u8()?;
+        let tac = reader.u8()?;
+        // Set directly, as writing TAC could clock TIMA
+        self.enabled = tac & 0b0100 == 0b0100;
+        self.input_clock = ClockFrequency::from_tac(tac);
+        self.reload_delay = reader.u8()?;
+        Ok(())
     }
 }
 
 impl ReadByte for Timer {
     fn read_byte(&self, address: u16) -> u8 {
         match address {
-            0xff04 => { self.div }
+            0xff04 => { (self.divider >> 8) as u8 }
             0xff05 => { self.tima }
             0xff06 => { self.tma }
             0xff07 => {
-                let mut value = 0;
-                if !self.stop { value |= 0b0100; }
+                let mut value = 0b1111_1000;
+                if self.enabled { value |= 0b0100; }
                 value |= match self.input_clock {
                     ClockFrequency::Hz4096 => 0b00,
                     ClockFrequency::Hz262144 => 0b01,
@@ -94,29 +141,97 @@ impl ReadByte for Timer {
                 };
                 value
             }
-            _ => { panic!("Invalid timer address: {:04x}", address); }
+            _ => { 0xff }
         }
-
     }
 }
 
 impl WriteByte for Timer {
     fn write_byte(&mut self, address: u16, value: u8) {
+        // Resetting DIV or changing TAC can pull the timer signal low, which
+        // counts as a falling edge
+        let before = self.timer_signal();
+
         match address {
-            0xff04 => { self.div = 0; }
-            0xff05 => { self.tima = value; }
+            0xff04 => { self.divider = 0; }
+            0xff05 => {
+                // Writing TIMA while a reload is pending cancels the reload
+                self.tima = value;
+                self.reload_delay = 0;
+            }
             0xff06 => { self.tma = value; }
             0xff07 => {
-                self.stop = (value & 0b0100) == 0;
-                self.input_clock = match value & 0b0011 {
-                    0b00 => ClockFrequency::Hz4096,
-                    0b01 => ClockFrequency::Hz262144,
-                    0b10 => ClockFrequency::Hz65536,
-                    0b11 => ClockFrequency::Hz16384,
-                    _ => { panic!("Invalid click frequency: {:04x}", value & 0b0011); }
-                }
+                self.enabled = (value & 0b0100) == 0b0100;
+                self.input_clock = ClockFrequency::from_tac(value);
             }
-            _ => { panic!("Invalid timer address: {:04x}", address); }
+            _ => {}
         }
+
+        if before && !self.timer_signal() {
+            self.inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory_map::{ReadByte, WriteByte};
+    use super::Timer;
+
+    #[test]
+    fn div_is_upper_byte_of_divider() {
+        let mut timer = Timer::new();
+        timer.step(255);
+        assert_eq!(timer.read_byte(0xff04), 0);
+        timer.step(1);
+        assert_eq!(timer.read_byte(0xff04), 1);
+        timer.write_byte(0xff04, 0x12);
+        assert_eq!(timer.read_byte(0xff04), 0);
+    }
+
+    #[test]
+    fn tima_counts_at_selected_frequency() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xff07, 0b101); // Enabled, 262144 Hz
+        for _ in 0..4 { timer.step(4); }
+        assert_eq!(timer.read_byte(0xff05), 1);
+        for _ in 0..4 { timer.step(4); }
+        assert_eq!(timer.read_byte(0xff05), 2);
+    }
+
+    #[test]
+    fn overflow_reloads_tma_after_delay() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xff05, 0xff);
+        timer.write_byte(0xff06, 0xab);
+        timer.write_byte(0xff07, 0b101);
+
+        assert_eq!(timer.step(16), 0);
+        assert_eq!(timer.read_byte(0xff05), 0x00);
+        assert_eq!(timer.step(4), 0b0000_0100);
+        assert_eq!(timer.read_byte(0xff05), 0xab);
+    }
+
+    #[test]
+    fn writing_tima_during_delay_cancels_reload() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xff05, 0xff);
+        timer.write_byte(0xff06, 0xab);
+        timer.write_byte(0xff07, 0b101);
+
+        timer.step(16);
+        timer.write_byte(0xff05, 0x42);
+        assert_eq!(timer.step(4), 0);
+        assert_eq!(timer.read_byte(0xff05), 0x42);
+    }
+
+    #[test]
+    fn resetting_div_can_clock_tima() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xff07, 0b101);
+        timer.step(8); // Bit 3 of the divider is now high
+        assert_eq!(timer.read_byte(0xff05), 0);
+        timer.write_byte(0xff04, 0);
+        assert_eq!(timer.read_byte(0xff05), 1);
     }
 }
@@ -0,0 +1,49 @@
+extern crate gbrs;
+
+mod common;
+
+use gbrs::cpu::CPU;
+use gbrs::debugger::Debugger;
+use std::path::Path;
+
+/// Emulated cycles a ROM gets to report, a minute of Game Boy time
+const TIMEOUT: u64 = 60 * 4_194_304;
+
+/// Runs until the ROM prints "Passed" or "Failed" over the serial port
+fn run(path: &Path) -> Result<(), String> {
+    let mut cpu: CPU = common::load(path)?;
+    let mut debugger = Debugger::disabled();
+    let mut printed = 0;
+
+    while cpu.cycles() < TIMEOUT {
+        cpu.step(&mut debugger).map_err(|error| error.to_string())?;
+
+        let output = cpu.mmu.serial.output();
+        if output.len() == printed {
+            continue;
+        }
+        printed = output.len();
+        let text = String::from_utf8_lossy(output);
+        if text.contains("Passed") {
+            return Ok(());
+        }
+        if text.contains("Failed") {
+            return Err(text.trim().replace('\n', " "));
+        }
+    }
+    Err(format!("Timed out, printed {:?}", String::from_utf8_lossy(cpu.mmu.serial.output())))
+}
+
+#[test]
+#[ignore = "needs Blargg's test ROMs, run with --ignored"]
+fn blargg_roms() {
+    let dir = common::rom_dir("BLARGG_ROMS", "blargg");
+    let roms = common::find_roms(&dir);
+    assert!(!roms.is_empty(), "No ROMs in {}, set BLARGG_ROMS to their directory", dir.display());
+
+    let results: Vec<_> = roms.into_iter().map(|rom| {
+        let result = run(&rom);
+        (rom, result)
+    }).collect();
+    common::report(&dir, &results);
+}
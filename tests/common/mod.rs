@@ -0,0 +1,59 @@
+// Shared by every integration test crate, each of which uses only some of it
+#![allow(dead_code)]
+
+use gbrs::cartridge::Cartridge;
+use gbrs::cpu::CPU;
+use gbrs::mmu::MMU;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Test ROMs aren't redistributable, so suites are read from a directory
+/// named by `variable`, or `tests/roms/<default>` when it isn't set
+pub fn rom_dir(variable: &str, default: &str) -> PathBuf {
+    env::var_os(variable).map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms").join(default))
+}
+
+/// Every .gb file under `dir`, sorted by path. Empty if `dir` doesn't exist.
+pub fn find_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = vec![];
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                roms.extend(find_roms(&path));
+            } else if path.extension().and_then(|extension| extension.to_str()) == Some("gb") {
+                roms.push(path);
+            }
+        }
+    }
+    roms.sort();
+    roms
+}
+
+/// Boots a ROM the way `main` does
+pub fn load(path: &Path) -> Result<CPU, String> {
+    let rom = fs::read(path).map_err(|error| error.to_string())?;
+    let cart = Cartridge::new(rom.into_boxed_slice()).map_err(|error| error.to_string())?;
+    let mut mmu: MMU = MMU::new();
+    mmu.load_cartridge(cart);
+    Ok(CPU::new(mmu))
+}
+
+/// Prints one row per ROM and panics listing the ROMs that didn't pass
+pub fn report(dir: &Path, results: &[(PathBuf, Result<(), String>)]) {
+    let name = |path: &PathBuf| path.strip_prefix(dir).unwrap_or(path).display().to_string();
+    let width = results.iter().map(|(path, _)| name(path).len()).max().unwrap_or(0);
+
+    for (path, result) in results {
+        match result {
+            Ok(()) => println!("{:width$}  ok", name(path), width = width),
+            Err(reason) => println!("{:width$}  FAILED  {}", name(path), reason, width = width)
+        }
+    }
+    let failed: Vec<_> = results.iter().filter(|(_, result)| result.is_err())
+        .map(|(path, _)| name(path)).collect();
+    println!("{} passed, {} failed", results.len() - failed.len(), failed.len());
+    assert!(failed.is_empty(), "Failing ROMs: {}", failed.join(", "));
+}
@@ -1,5 +1,6 @@
 extern crate gbrs;
 use gbrs::cpu::CPU;
+use gbrs::debugger::Debugger;
 use gbrs::mmu::MMU;
 use gbrs::cartridge::Cartridge;
 use std::num::Wrapping as W;
@@ -57,8 +58,8 @@ macro_rules! make_rom {
             $($rest),*
         ];
         let mut checksum = 0u8;
-        for i in (0x0134..0x014c) {
-            checksum = (W(checksum) + W(rom[i])).0;
+        for i in 0x0134..0x014d {
+            checksum = (W(checksum) - W(rom[i]) - W(1)).0;
         }
         rom[0x014d] = checksum;
         rom.into_boxed_slice()
@@ -69,33 +70,35 @@ macro_rules! make_rom {
 #[ignore]
 fn power_up_sequence() {
     let mut rom = make_rom!(0x10);
-    let cart = Cartridge::new(rom);
+    let cart = Cartridge::new(rom).unwrap();
     let mut mmu: MMU = MMU::new();
     mmu.load_cartridge(cart);
     let mut cpu: CPU = CPU::new(mmu);
+    let mut debugger = Debugger::new();
     let mut i = 0;
     while !cpu.stopped {
-        cpu.step();
+        cpu.step(&mut debugger).unwrap();
     }
 
-    assert_eq!(cpu.a, 0x01);
-    assert_eq!(cpu.b, 0xb0);
-    assert_eq!(cpu.sp, 0xfffe);
+    assert_eq!(cpu.registers.a, 0x01);
+    assert_eq!(cpu.registers.b, 0xb0);
+    assert_eq!(cpu.registers.sp, 0xfffe);
 }
 
 #[test]
 fn ld_bc_immediate_word() {
     let mut rom = make_rom!(0x01, 0xfe, 0xca, 0x10);
 
-    let cart = Cartridge::new(rom);
+    let cart = Cartridge::new(rom).unwrap();
     let mut mmu: MMU = MMU::new();
     mmu.load_cartridge(cart);
     let mut cpu: CPU = CPU::new(mmu);
+    let mut debugger = Debugger::new();
     while !cpu.stopped {
-        cpu.step();
+        cpu.step(&mut debugger).unwrap();
     }
-    assert_eq!(cpu.b, 0xca);
-    assert_eq!(cpu.c, 0xfe);
+    assert_eq!(cpu.registers.b, 0xca);
+    assert_eq!(cpu.registers.c, 0xfe);
 }
 
 #[test]
@@ -117,20 +120,21 @@ fn registers_8bit_wrap_around_upon_overflow() {
         0x3c,       // INC A
         0x10        // STOP
     );
-    let cart = Cartridge::new(rom);
+    let cart = Cartridge::new(rom).unwrap();
     let mut mmu: MMU = MMU::new();
     mmu.load_cartridge(cart);
     let mut cpu: CPU = CPU::new(mmu);
+    let mut debugger = Debugger::new();
     while !cpu.stopped {
-        cpu.step();
+        cpu.step(&mut debugger).unwrap();
     }
-    assert_eq!(cpu.b, 0);
-    assert_eq!(cpu.c, 0);
-    assert_eq!(cpu.d, 0);
-    assert_eq!(cpu.e, 0);
-    assert_eq!(cpu.h, 0);
-    assert_eq!(cpu.l, 0);
-    assert_eq!(cpu.a, 0);
+    assert_eq!(cpu.registers.b, 0);
+    assert_eq!(cpu.registers.c, 0);
+    assert_eq!(cpu.registers.d, 0);
+    assert_eq!(cpu.registers.e, 0);
+    assert_eq!(cpu.registers.h, 0);
+    assert_eq!(cpu.registers.l, 0);
+    assert_eq!(cpu.registers.a, 0);
 }
 
 #[test]
@@ -152,20 +156,21 @@ fn registers_8bit_wrap_around_upon_underflow() {
         0x3d,       // DEC A
         0x10        // STOP
     );
-    let cart = Cartridge::new(rom);
+    let cart = Cartridge::new(rom).unwrap();
     let mut mmu: MMU = MMU::new();
     mmu.load_cartridge(cart);
     let mut cpu: CPU = CPU::new(mmu);
+    let mut debugger = Debugger::new();
     while !cpu.stopped {
-        cpu.step();
+        cpu.step(&mut debugger).unwrap();
     }
-    assert_eq!(cpu.b, 255);
-    assert_eq!(cpu.c, 255);
-    assert_eq!(cpu.d, 255);
-    assert_eq!(cpu.e, 255);
-    assert_eq!(cpu.h, 255);
-    assert_eq!(cpu.l, 255);
-    assert_eq!(cpu.a, 255);
+    assert_eq!(cpu.registers.b, 255);
+    assert_eq!(cpu.registers.c, 255);
+    assert_eq!(cpu.registers.d, 255);
+    assert_eq!(cpu.registers.e, 255);
+    assert_eq!(cpu.registers.h, 255);
+    assert_eq!(cpu.registers.l, 255);
+    assert_eq!(cpu.registers.a, 255);
 }
 
 #[test]
@@ -185,20 +190,21 @@ fn registers_16bit_wrap_around_upon_overflow() {
 
         0x10                // STOP
     );
-    let cart = Cartridge::new(rom);
+    let cart = Cartridge::new(rom).unwrap();
     let mut mmu: MMU = MMU::new();
     mmu.load_cartridge(cart);
     let mut cpu: CPU = CPU::new(mmu);
+    let mut debugger = Debugger::new();
     while !cpu.stopped {
-        cpu.step();
+        cpu.step(&mut debugger).unwrap();
     }
-    assert_eq!(cpu.b, 0);
-    assert_eq!(cpu.c, 0);
-    assert_eq!(cpu.d, 0);
-    assert_eq!(cpu.e, 0);
-    assert_eq!(cpu.h, 0);
-    assert_eq!(cpu.l, 0);
-    assert_eq!(cpu.sp, 0);
+    assert_eq!(cpu.registers.b, 0);
+    assert_eq!(cpu.registers.c, 0);
+    assert_eq!(cpu.registers.d, 0);
+    assert_eq!(cpu.registers.e, 0);
+    assert_eq!(cpu.registers.h, 0);
+    assert_eq!(cpu.registers.l, 0);
+    assert_eq!(cpu.registers.sp, 0);
 }
 
 #[test]
@@ -218,18 +224,19 @@ fn registers_16bit_wrap_around_upon_underflow() {
 
         0x10                // STOP
     );
-    let cart = Cartridge::new(rom);
+    let cart = Cartridge::new(rom).unwrap();
     let mut mmu: MMU = MMU::new();
     mmu.load_cartridge(cart);
     let mut cpu: CPU = CPU::new(mmu);
+    let mut debugger = Debugger::new();
     while !cpu.stopped {
-        cpu.step();
+        cpu.step(&mut debugger).unwrap();
     }
-    assert_eq!(cpu.b, 255);
-    assert_eq!(cpu.c, 255);
-    assert_eq!(cpu.d, 255);
-    assert_eq!(cpu.e, 255);
-    assert_eq!(cpu.h, 255);
-    assert_eq!(cpu.l, 255);
-    assert_eq!(cpu.sp, 65535);
+    assert_eq!(cpu.registers.b, 255);
+    assert_eq!(cpu.registers.c, 255);
+    assert_eq!(cpu.registers.d, 255);
+    assert_eq!(cpu.registers.e, 255);
+    assert_eq!(cpu.registers.h, 255);
+    assert_eq!(cpu.registers.l, 255);
+    assert_eq!(cpu.registers.sp, 65535);
 }
@@ -0,0 +1,51 @@
+extern crate gbrs;
+
+mod common;
+
+use gbrs::cpu::CPU;
+use gbrs::debugger::Debugger;
+use gbrs::memory_map::ReadByte;
+use std::path::Path;
+
+/// Emulated cycles a ROM gets to reach its result, 30 seconds of Game Boy time
+const TIMEOUT: u64 = 30 * 4_194_304;
+
+/// `LD B,B`, which the test ROMs execute once they have a result
+const DEBUG_BREAK: u8 = 0x40;
+
+/// Passing tests load the start of the Fibonacci sequence into B, C, D, E, H and L
+const PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+/// Failing tests load 0x42 into all of them
+const FAIL: [u8; 6] = [0x42; 6];
+
+fn run(path: &Path) -> Result<(), String> {
+    let mut cpu: CPU = common::load(path)?;
+    let mut debugger = Debugger::disabled();
+
+    while cpu.cycles() < TIMEOUT {
+        if !cpu.halted && cpu.mmu.read_byte(cpu.registers.pc) == DEBUG_BREAK {
+            let r = &cpu.registers;
+            return match [r.b, r.c, r.d, r.e, r.h, r.l] {
+                PASS => Ok(()),
+                FAIL => Err("Failed".to_string()),
+                registers => Err(format!("Unexpected registers {:02x?} at {:04x}", registers, r.pc))
+            };
+        }
+        cpu.step(&mut debugger).map_err(|error| error.to_string())?;
+    }
+    Err(format!("Timed out at {:04x}", cpu.registers.pc))
+}
+
+#[test]
+#[ignore = "needs Mooneye's acceptance ROMs, run with --ignored"]
+fn mooneye_acceptance() {
+    let dir = common::rom_dir("MOONEYE_ROMS", "mooneye/acceptance");
+    let roms = common::find_roms(&dir);
+    assert!(!roms.is_empty(), "No ROMs in {}, set MOONEYE_ROMS to their directory", dir.display());
+
+    let results: Vec<_> = roms.into_iter().map(|rom| {
+        let result = run(&rom);
+        (rom, result)
+    }).collect();
+    common::report(&dir, &results);
+}
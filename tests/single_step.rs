@@ -0,0 +1,311 @@
+extern crate gbrs;
+
+mod common;
+
+use gbrs::bus::FlatBus;
+use gbrs::cpu::CPU;
+use gbrs::debugger::NoDebugger;
+use gbrs::memory_map::{ReadByte, WriteByte};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The subset of JSON the test vectors use
+#[derive(Debug)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>)
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { text: text.as_bytes(), position: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.position != parser.text.len() {
+            return Err(parser.error("Trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref fields) => fields.iter().find(|field| field.0 == key).map(|field| &field.1),
+            _ => None
+        }
+    }
+
+    fn number(&self) -> Option<u16> {
+        match *self {
+            Json::Number(n) => Some(n as u16),
+            Json::Bool(b) => Some(b as u16),
+            _ => None
+        }
+    }
+
+    fn array(&self) -> &[Json] {
+        match *self {
+            Json::Array(ref values) => values,
+            _ => &[]
+        }
+    }
+
+    fn string(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.position)
+    }
+
+    fn whitespace(&mut self) {
+        while self.position < self.text.len() && (self.text[self.position] as char).is_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.whitespace();
+        self.text.get(self.position).cloned()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("Expected '{}'", byte as char)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json, String> {
+        if self.text[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("Unknown literal"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(_) => self.number(),
+            None => Err(self.error("Unexpected end of input"))
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut fields = vec![];
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(b':')?;
+            fields.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                _ => break
+            }
+        }
+        self.expect(b'}')?;
+        Ok(Json::Object(fields))
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut values = vec![];
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                _ => break
+            }
+        }
+        self.expect(b']')?;
+        Ok(Json::Array(values))
+    }
+
+    // Test names and cycle kinds never contain escapes
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let start = self.position;
+        while self.position < self.text.len() && self.text[self.position] != b'"' {
+            self.position += 1;
+        }
+        let string = String::from_utf8_lossy(&self.text[start..self.position]).into_owned();
+        self.expect(b'"')?;
+        Ok(string)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while self.position < self.text.len() && b"+-.eE0123456789".contains(&self.text[self.position]) {
+            self.position += 1;
+        }
+        String::from_utf8_lossy(&self.text[start..self.position]).parse()
+            .map(Json::Number)
+            .map_err(|_| self.error("Invalid number"))
+    }
+}
+
+const REGISTERS: [&str; 11] = ["pc", "sp", "a", "b", "c", "d", "e", "f", "h", "l", "ime"];
+
+fn register(cpu: &CPU<FlatBus>, name: &str) -> u16 {
+    let r = &cpu.registers;
+    match name {
+        "pc" => r.pc,
+        "sp" => r.sp,
+        "a" => r.a as u16,
+        "b" => r.b as u16,
+        "c" => r.c as u16,
+        "d" => r.d as u16,
+        "e" => r.e as u16,
+        "f" => r.f() as u16,
+        "h" => r.h as u16,
+        "l" => r.l as u16,
+        "ime" => cpu.interrupts as u16,
+        _ => unreachable!()
+    }
+}
+
+fn set_register(cpu: &mut CPU<FlatBus>, name: &str, value: u16) {
+    match name {
+        "pc" => cpu.registers.pc = value,
+        "sp" => cpu.registers.sp = value,
+        "a" => cpu.registers.a = value as u8,
+        "b" => cpu.registers.b = value as u8,
+        "c" => cpu.registers.c = value as u8,
+        "d" => cpu.registers.d = value as u8,
+        "e" => cpu.registers.e = value as u8,
+        "f" => cpu.registers.set_f(value as u8),
+        "h" => cpu.registers.h = value as u8,
+        "l" => cpu.registers.l = value as u8,
+        "ime" => cpu.interrupts = value != 0,
+        _ => unreachable!()
+    }
+}
+
+fn ram(state: &Json) -> Vec<(u16, u8)> {
+    state.get("ram").map_or(&[][..], |ram| ram.array()).iter().filter_map(|entry| {
+        let entry = entry.array();
+        match (entry.first().and_then(Json::number), entry.get(1).and_then(Json::number)) {
+            (Some(address), Some(value)) => Some((address, value as u8)),
+            _ => None
+        }
+    }).collect()
+}
+
+/// Runs a single test vector, describing the first difference from its final state
+fn run_test(test: &Json) -> Result<(), String> {
+    let name = test.get("name").and_then(Json::string).unwrap_or("?");
+    let initial = test.get("initial").ok_or("Missing initial state")?;
+    let expected = test.get("final").ok_or("Missing final state")?;
+
+    let mut cpu = CPU::new(FlatBus::new());
+    for &(address, value) in &ram(initial) {
+        cpu.mmu.write_byte(address, value);
+    }
+    for &name in &REGISTERS {
+        if let Some(value) = initial.get(name).and_then(Json::number) {
+            set_register(&mut cpu, name, value);
+        }
+    }
+
+    // Some versions of the vectors model the overlapped opcode fetch, with PC
+    // already past the opcode at the start and past the next one at the end
+    let opcode = name.split_whitespace().next().and_then(|op| u8::from_str_radix(op, 16).ok());
+    let pc = cpu.registers.pc;
+    let prefetched = opcode.is_some() && cpu.mmu.read_byte(pc) != opcode.unwrap()
+        && cpu.mmu.read_byte(pc.wrapping_sub(1)) == opcode.unwrap();
+    if prefetched {
+        cpu.registers.pc = pc.wrapping_sub(1);
+    }
+
+    cpu.step(&mut NoDebugger).map_err(|error| format!("{}: {}", name, error))?;
+    if prefetched {
+        cpu.registers.pc = cpu.registers.pc.wrapping_add(1);
+    }
+
+    for &register_name in &REGISTERS {
+        if let Some(value) = expected.get(register_name).and_then(Json::number) {
+            let actual = register(&cpu, register_name);
+            if actual != value {
+                return Err(format!("{}: {} is {:x}, expected {:x}", name, register_name, actual, value));
+            }
+        }
+    }
+    for &(address, value) in &ram(expected) {
+        let actual = cpu.mmu.read_byte(address);
+        if actual != value {
+            return Err(format!("{}: ({:04x}) is {:02x}, expected {:02x}", name, address, actual, value));
+        }
+    }
+    let cycles = test.get("cycles").map_or(0, |cycles| cycles.array().len() as u64) * 4;
+    if cpu.cycles() != cycles {
+        return Err(format!("{}: took {} cycles, expected {}", name, cpu.cycles(), cycles));
+    }
+    Ok(())
+}
+
+/// Runs every vector in one opcode's file, stopping at the first failure
+fn run_file(path: &Path) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let tests = Json::parse(&text)?;
+    for test in tests.array() {
+        run_test(test)?;
+    }
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs the SingleStepTests vectors, run with --ignored"]
+fn single_step_tests() {
+    let dir = common::rom_dir("SM83_TESTS", "sm83");
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir).map(|entries| {
+        entries.filter_map(|entry| entry.ok()).map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|extension| extension.to_str()) == Some("json"))
+            .collect()
+    }).unwrap_or_default();
+    assert!(!files.is_empty(), "No test vectors in {}, set SM83_TESTS to their directory", dir.display());
+    files.sort();
+
+    let results: Vec<_> = files.into_iter().map(|file| {
+        let result = run_file(&file);
+        (file, result)
+    }).collect();
+    common::report(&dir, &results);
+}
+
+#[test]
+fn parses_json() {
+    let json = Json::parse(r#"{"name": "cb 00 0000", "ram": [[1, 2], [3, 255]], "ime": 1, "x": [null, true, -1.5e1]}"#).unwrap();
+    assert_eq!(json.get("name").and_then(Json::string), Some("cb 00 0000"));
+    assert_eq!(ram(&json), vec![(1, 2), (3, 255)]);
+    assert_eq!(json.get("ime").and_then(Json::number), Some(1));
+    assert!(Json::parse("[1, 2").is_err());
+}